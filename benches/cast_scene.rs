@@ -0,0 +1,68 @@
+// Times `cast_scene` over a few scene sizes and column widths in a 1024x768 view.
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use nannou::prelude::*;
+use nannou::rand::rngs::StdRng;
+use nannou::rand::{Rng, SeedableRng};
+use rust_visual_nannou::{
+    cast_scene, Boundary, BoundaryIndex, ColumnAngles, Player, World, MAX_DISTANCE,
+};
+use std::collections::HashSet;
+
+// The window's edges plus `walls` short random walls, the same every run.
+fn random_walls(window: Rect, walls: usize) -> Vec<Boundary> {
+    let mut rng = StdRng::seed_from_u64(0);
+
+    let mut boundaries = Boundary::window_edges(window);
+    boundaries.extend((0..walls).map(|_| {
+        let start = pt2(
+            rng.gen_range(window.left()..window.right()),
+            rng.gen_range(window.bottom()..window.top()),
+        );
+        let end = start + vec2(rng.gen_range(-80.0..80.0), rng.gen_range(-80.0..80.0));
+        Boundary::new(start, end)
+    }));
+    boundaries
+}
+
+fn bench_cast_scene(c: &mut Criterion) {
+    let window = Rect::from_w_h(1024.0, 768.0);
+    let player = Player::new();
+    let hidden_layers = HashSet::new();
+    let mut group = c.benchmark_group("cast_scene");
+
+    for walls in [16, 128, 1024] {
+        let boundaries = random_walls(window, walls);
+        let index = BoundaryIndex::build(&boundaries);
+
+        for resolution in [1, 5, 10] {
+            let angles = ColumnAngles::new(60.0, resolution, window.w());
+            let id = format!("{} walls, {}px columns", walls, resolution);
+
+            // Counts the columns that hit so the work can't be optimised away.
+            group.bench_function(BenchmarkId::from_parameter(id), |b| {
+                b.iter(|| {
+                    let world = World {
+                        boundaries: &boundaries,
+                        hidden_layers: &hidden_layers,
+                        circles: &[],
+                        doors: &[],
+                        portals: &[],
+                        index: &index,
+                    };
+                    let columns = cast_scene(&player, world, &angles, 4, MAX_DISTANCE, 1);
+                    black_box(
+                        columns
+                            .iter()
+                            .filter(|column| column.length.is_some())
+                            .count(),
+                    )
+                })
+            });
+        }
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_cast_scene);
+criterion_main!(benches);
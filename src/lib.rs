@@ -0,0 +1,2484 @@
+//! The ray casting core: walls, the spatial index, rays and the per-column cast
+//! that the app draws from and `benches/cast_scene.rs` times.
+
+use nannou::image::{self, RgbaImage};
+use nannou::prelude::*;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::sync::Arc;
+
+pub const PLAYER_RADIUS: f32 = 8.0;
+pub const GRID_CELL_SIZE: f32 = 50.0;
+pub const INDEX_CELL_SIZE: f32 = 100.0;
+// As far as the default fog reaches; beyond it everything is fog colour anyway.
+pub const MAX_DISTANCE: f32 = 1500.0;
+pub const DEFAULT_AMBIENT: f32 = 0.2;
+pub const REFLECT_OFFSET: f32 = 0.01;
+pub const DOOR_SPEED: f32 = 1.0;
+pub const PARALLEL_EPSILON: f32 = 1e-6;
+/// Slack, in world units, on where along a segment a hit may land, and how close
+/// two hits must be to count as equally near.
+pub const CORNER_EPSILON: f32 = 1e-3;
+pub const WALL_HEIGHT: f32 = 100.0;
+pub const DEFAULT_NEAR_CLIP: f32 = 2.0;
+pub const MAX_GLASS_LAYERS: usize = 4;
+pub const SHININESS: i32 = 16;
+pub const BOB_HEIGHT: f32 = 6.0;
+pub const BOB_STRIDE: f32 = 0.08;
+pub const BOB_SETTLE: f32 = 8.0;
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Player {
+    pub pos: Point2,
+    pub look_dir: Vec2,
+    #[serde(default)]
+    pub pitch: f32,
+    /// Where turning input points; `look_dir` follows it smoothly.
+    #[serde(skip)]
+    pub target_look_dir: Vec2,
+    #[serde(skip)]
+    pub velocity: Vec2,
+    /// Walk cycle, advanced by distance travelled.
+    #[serde(skip)]
+    pub bob_phase: f32,
+    /// How much of the bob is applied, easing to 0 when the player stands still.
+    #[serde(skip)]
+    pub bob_amount: f32,
+    /// Distance walked since the last footstep.
+    #[serde(skip)]
+    pub stride_travelled: f32,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Boundary {
+    pub origin: Point2,
+    pub dir: Vec2,
+    pub length: f32,
+    #[serde(skip)]
+    pub texture: Option<Arc<RgbaImage>>,
+    /// Where `texture` was loaded from, as written in the map, so it can be saved
+    /// back out.
+    #[serde(default)]
+    pub texture_path: Option<String>,
+    pub color: Rgb,
+    pub reflective: bool,
+    pub window_edge: bool,
+    /// Multiplier on the projected wall height; walls stand on the floor either way.
+    #[serde(default = "default_wall_height")]
+    pub height: f32,
+    /// Below 1 the wall is glass: it tints what's behind it rather than hiding it.
+    #[serde(default = "default_opacity")]
+    pub opacity: f32,
+    #[serde(default)]
+    pub motion: Option<BoundaryMotion>,
+    #[serde(default)]
+    pub material: Material,
+    /// Whether the player bumps into it; rays see it either way.
+    #[serde(default = "default_solid")]
+    pub solid: bool,
+    /// Group the wall belongs to, so whole groups can be hidden together.
+    #[serde(default)]
+    pub layer: u32,
+}
+
+/// How a surface responds to light. The default (white albedo, no ambient, no
+/// specular) is the plain diffuse look.
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub struct Material {
+    /// Multiplies the boundary's `color`, so glass can be given its own tint.
+    #[serde(default = "default_albedo")]
+    pub albedo: Rgb,
+    /// Light the surface shows even when nothing lights it.
+    pub ambient: f32,
+    /// Strength of the highlight where the view reflects straight back at the lamp.
+    pub specular: f32,
+}
+
+/// Slides a boundary from `rest` to `rest + offset` and back once per `period`
+/// seconds, easing at both ends.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct BoundaryMotion {
+    pub rest: Point2,
+    pub offset: Vec2,
+    pub period: f32,
+}
+
+impl Default for Material {
+    fn default() -> Material {
+        Material {
+            albedo: default_albedo(),
+            ambient: 0.0,
+            specular: 0.0,
+        }
+    }
+}
+
+pub fn default_albedo() -> Rgb {
+    rgb(1.0, 1.0, 1.0)
+}
+
+pub fn default_wall_height() -> f32 {
+    1.0
+}
+
+pub fn default_opacity() -> f32 {
+    1.0
+}
+
+pub fn default_solid() -> bool {
+    true
+}
+
+pub struct BoundaryIndex {
+    pub origin: Point2,
+    pub cell_size: f32,
+    pub cols: i32,
+    pub rows: i32,
+    pub cells: Vec<Vec<usize>>,
+}
+
+pub struct Grid {
+    pub cells: Vec<Vec<u8>>,
+    pub cell_size: f32,
+}
+
+/// Which way a wall faces: for the grid, which kind of grid line the DDA crossed
+/// into it, and for segments, whichever axis the wall runs closer to.
+#[derive(Clone, Copy)]
+pub enum WallSide {
+    /// A vertical line, so the face points east or west.
+    EastWest,
+    /// A horizontal line, so the face points north or south.
+    NorthSouth,
+}
+
+impl WallSide {
+    /// The face normal, pointing back along `dir`.
+    pub fn normal(self, dir: Vec2) -> Vec2 {
+        match self {
+            WallSide::EastWest => vec2(-dir.x.signum(), 0.0),
+            WallSide::NorthSouth => vec2(0.0, -dir.y.signum()),
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct CircleBoundary {
+    pub center: Point2,
+    pub radius: f32,
+    pub color: Rgb,
+}
+
+/// Two linked openings: whatever enters one side comes out of the other, as if
+/// the two segments were laid on top of each other facing opposite ways.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Portal {
+    pub a: Boundary,
+    pub b: Boundary,
+}
+
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum DoorState {
+    Closed,
+    Opening,
+    Open,
+    Closing,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Door {
+    pub boundary: Boundary,
+    pub open_amount: f32,
+    pub state: DoorState,
+}
+
+pub enum Obstacle<'a> {
+    Segment(&'a Boundary),
+    Circle(&'a CircleBoundary),
+    Door(&'a Door),
+}
+
+#[derive(Clone, Copy)]
+pub struct World<'a> {
+    pub boundaries: &'a [Boundary],
+    /// Layers whose walls are neither seen nor collided with.
+    pub hidden_layers: &'a HashSet<u32>,
+    pub circles: &'a [CircleBoundary],
+    pub doors: &'a [Door],
+    pub portals: &'a [Portal],
+    pub index: &'a BoundaryIndex,
+}
+
+#[derive(Clone)]
+pub struct Ray {
+    pub origin: Point2,
+    pub dir: Vec2,
+    pub end: Option<Point2>,
+    pub length: Option<f32>,
+    pub luminosity: Option<f32>,
+    pub offset: Option<f32>,
+    pub texture: Option<Arc<RgbaImage>>,
+    pub color: Rgb,
+    pub normal: Option<Vec2>,
+    pub reflective: bool,
+    pub height: f32,
+    pub opacity: f32,
+    pub material: Material,
+    /// Which way the face that was hit points, for circles left as `None`.
+    pub side: Option<WallSide>,
+    /// Where each leg of the path ended, where the next one starts and its
+    /// direction. The two points differ only when the ray went through a portal.
+    pub bounces: Vec<(Point2, Point2, Vec2)>,
+    /// Glass passed through on the way to the final hit, nearest first.
+    pub glass: Vec<GlassLayer>,
+}
+
+/// Where a ray meets an obstacle.
+#[derive(Clone, Copy)]
+pub struct Hit {
+    pub point: Point2,
+    /// Distance along the ray.
+    pub t: f32,
+    /// Distance along the boundary from its origin; around a circle, the arc
+    /// length anticlockwise from its rightmost point.
+    pub k: f32,
+    /// The surface normal, turned to face back along the ray.
+    pub normal: Vec2,
+}
+
+#[derive(Clone)]
+pub struct GlassLayer {
+    pub point: Point2,
+    /// Distance travelled along the ray to reach this pane.
+    pub distance: f32,
+    pub dir: Vec2,
+    pub normal: Vec2,
+    pub color: Rgb,
+    pub material: Material,
+    pub opacity: f32,
+    pub height: f32,
+}
+
+impl Default for Player {
+    fn default() -> Player {
+        Player::new()
+    }
+}
+
+impl Player {
+    pub fn show_player(&self, draw: &Draw, size: f32) {
+        draw.ellipse().w_h(size, size).xy(self.pos);
+
+        draw.line()
+            .start(self.pos)
+            .end(self.pos + (50.0 * self.look_dir))
+            .weight(2.0)
+            .color(RED);
+    }
+
+    // Returns how far the player actually moved, in the frame they end up in if
+    // they went through a portal.
+    pub fn update_player_pos(&mut self, vel: Vec2, world: World) -> Vec2 {
+        // Step at most half the radius at a time so fast moves can't skip over a wall.
+        let steps = (vel.length() / (PLAYER_RADIUS / 2.0)).ceil().max(1.0) as i32;
+        let mut step = vel / steps as f32;
+        let mut moved = vec2(0.0, 0.0);
+
+        for _ in 0..steps {
+            let mut before = self.pos;
+            self.pos += step;
+
+            if let Some((entry, exit)) = world.portal_crossed(before, self.pos) {
+                before = portal_point(entry, exit, before);
+                self.pos = portal_point(entry, exit, self.pos);
+                step = portal_dir(entry, exit, step);
+                moved = portal_dir(entry, exit, moved);
+                self.look_dir = portal_dir(entry, exit, self.look_dir);
+                self.target_look_dir = portal_dir(entry, exit, self.target_look_dir);
+            }
+
+            self.resolve_collisions(world);
+            moved += self.pos - before;
+        }
+
+        moved
+    }
+
+    pub fn resolve_collisions(&mut self, world: World) {
+        // Pushing out along each wall's normal leaves the tangential part of the move,
+        // which gives sliding. A few passes settle corners where two walls overlap.
+        for _ in 0..4 {
+            let mut collided = false;
+
+            for obstacle in world.obstacles().filter(Obstacle::is_solid) {
+                let offset = self.pos - obstacle.closest_point(self.pos);
+                let distance = offset.length();
+
+                if distance < PLAYER_RADIUS {
+                    let normal = if distance > 0.0 {
+                        offset / distance
+                    } else {
+                        obstacle.normal_at(self.pos)
+                    };
+
+                    self.pos += normal * (PLAYER_RADIUS - distance);
+                    collided = true;
+                }
+            }
+
+            if !collided {
+                break;
+            }
+        }
+    }
+
+    pub fn clamp_to(&mut self, bounds: Rect) {
+        let inner = bounds.pad(PLAYER_RADIUS);
+
+        if inner.w() > 0.0 && inner.h() > 0.0 {
+            self.pos = self.pos.clamp(inner.bottom_left(), inner.top_right());
+        }
+    }
+
+    pub fn turn(&mut self, d_theta: f32) {
+        // A freshly loaded player has no target yet.
+        if self.target_look_dir == Vec2::ZERO {
+            self.target_look_dir = self.look_dir;
+        }
+
+        self.target_look_dir = self.target_look_dir.rotate(d_theta).normalize();
+    }
+
+    // Snaps straight to facing `target`, skipping the smoothing. Looking at the
+    // player's own position leaves the direction as it was.
+    pub fn look_at(&mut self, target: Point2) {
+        if let Some(dir) = (target - self.pos).try_normalize() {
+            self.look_dir = dir;
+            self.target_look_dir = dir;
+        }
+    }
+
+    // Eases `look_dir` towards `target_look_dir` by at most `max_turn` radians.
+    // `angle_between` is signed and within -PI..PI, so this always turns the short
+    // way round.
+    pub fn turn_towards_target(&mut self, max_turn: f32) {
+        if self.target_look_dir == Vec2::ZERO {
+            return;
+        }
+
+        let angle = self.look_dir.angle_between(self.target_look_dir);
+        self.look_dir = self.look_dir.rotate(angle.clamp(-max_turn, max_turn));
+        self.look_dir = self.look_dir.normalize();
+    }
+
+    pub fn camera_state(&self) -> [f32; 6] {
+        [
+            self.pos.x,
+            self.pos.y,
+            self.look_dir.x,
+            self.look_dir.y,
+            self.pitch,
+            BOB_HEIGHT * self.bob_amount * self.bob_phase.sin(),
+        ]
+    }
+
+    pub fn update_player_pitch(&mut self, d_pitch: f32) {
+        self.pitch = (self.pitch + d_pitch).clamp(-1.0, 1.0);
+    }
+
+    // Returns how many footsteps the move completed.
+    pub fn update_stride(&mut self, moved: f32, stride: f32) -> u32 {
+        self.stride_travelled += moved;
+        let steps = (self.stride_travelled / stride).floor();
+        self.stride_travelled -= steps * stride;
+
+        steps as u32
+    }
+
+    pub fn update_bob(&mut self, moved: f32, dt: f32) {
+        let target = if moved > 0.01 { 1.0 } else { 0.0 };
+
+        self.bob_phase = (self.bob_phase + moved * BOB_STRIDE) % TAU;
+        self.bob_amount += (target - self.bob_amount) * (1.0 - (-BOB_SETTLE * dt).exp());
+    }
+
+    // Pseudo-pitch: looking up slides the whole scene down, by at most a quarter
+    // of the window so walls never leave the screen. The view bob rides on top.
+    pub fn horizon(&self, window: Rect) -> f32 {
+        -self.pitch * window.h() / 4.0 + BOB_HEIGHT * self.bob_amount * self.bob_phase.sin()
+    }
+
+    pub fn new() -> Player {
+        Player {
+            pos: pt2(0.0, 0.0),
+            look_dir: vec2(1.0, 0.0),
+            pitch: 0.0,
+            target_look_dir: vec2(1.0, 0.0),
+            velocity: vec2(0.0, 0.0),
+            bob_phase: 0.0,
+            bob_amount: 0.0,
+            stride_travelled: 0.0,
+        }
+    }
+}
+
+impl Ray {
+    pub fn new(player: &Player, d_theta: f32) -> Ray {
+        Ray::from_origin(player.pos, player.look_dir.rotate(d_theta).normalize())
+    }
+
+    pub fn from_origin(origin: Point2, dir: Vec2) -> Ray {
+        Ray {
+            origin,
+            dir,
+            end: None,
+            length: None,
+            luminosity: None,
+            offset: None,
+            texture: None,
+            color: rgb(1.0, 1.0, 1.0),
+            normal: None,
+            reflective: false,
+            height: 1.0,
+            opacity: 1.0,
+            material: Material::default(),
+            side: None,
+            bounces: Vec::new(),
+            glass: Vec::new(),
+        }
+    }
+
+    // Hits more than `max_distance` along the ray, counting any bounces, are misses.
+    pub fn cast(
+        player: &Player,
+        d_theta: f32,
+        world: World,
+        max_bounces: u32,
+        max_distance: f32,
+    ) -> Ray {
+        let mut ray = Ray::new(player, d_theta);
+        let mut probe = Ray::new(player, d_theta);
+        let mut traveled = 0.0;
+
+        let mut bounces = 0;
+
+        loop {
+            probe.find_nearest(world, max_distance - traveled);
+
+            let nearest = probe.length.unwrap_or(f32::INFINITY);
+            let portal = world
+                .portals
+                .iter()
+                .flat_map(|portal| portal.sides())
+                .filter_map(|(entry, exit)| {
+                    let hit = probe.intersect(entry)?;
+                    Some((hit.t, hit.point, entry, exit))
+                })
+                .filter(|&(distance, ..)| distance < nearest && traveled + distance <= max_distance)
+                .min_by(|a, b| a.0.total_cmp(&b.0));
+
+            if let Some((distance, point, entry, exit)) = portal {
+                if bounces == max_bounces {
+                    break;
+                }
+
+                let origin = portal_point(entry, exit, point);
+                let dir = portal_dir(entry, exit, probe.dir);
+                ray.bounces.push((point, origin, dir));
+                probe = Ray::from_origin(origin + REFLECT_OFFSET * dir, dir);
+                traveled += distance + REFLECT_OFFSET;
+                bounces += 1;
+                continue;
+            }
+
+            let (end, length, normal) = match (probe.end, probe.length, probe.normal) {
+                (Some(end), Some(length), Some(normal)) => (end, length, normal),
+                _ => break,
+            };
+            traveled += length;
+
+            if traveled > max_distance {
+                break;
+            }
+
+            if probe.reflective && bounces < max_bounces {
+                // Nudge the new origin off the mirror so it can't immediately hit itself.
+                let dir = probe.dir - 2.0 * probe.dir.dot(normal) * normal;
+                ray.bounces.push((end, end, dir));
+                probe = Ray::from_origin(end + REFLECT_OFFSET * dir, dir);
+                traveled += REFLECT_OFFSET;
+                bounces += 1;
+                continue;
+            }
+
+            if probe.opacity < 1.0 && ray.glass.len() < MAX_GLASS_LAYERS {
+                ray.glass.push(GlassLayer {
+                    point: end,
+                    distance: traveled,
+                    dir: probe.dir,
+                    normal,
+                    color: probe.color,
+                    material: probe.material,
+                    opacity: probe.opacity,
+                    height: probe.height,
+                });
+                probe = Ray::from_origin(end + REFLECT_OFFSET * probe.dir, probe.dir);
+                traveled += REFLECT_OFFSET;
+                continue;
+            }
+
+            ray.end = Some(end);
+            ray.length = Some(traveled);
+            ray.luminosity = Some(luminosity(traveled));
+            ray.offset = probe.offset;
+            ray.texture = probe.texture;
+            ray.color = probe.color;
+            ray.normal = probe.normal;
+            ray.height = probe.height;
+            ray.material = probe.material;
+            ray.side = probe.side;
+            break;
+        }
+
+        ray
+    }
+
+    // Finds the nearest hit on a fresh ray. It matches testing every obstacle in
+    // `World::obstacles` order, the first of equally near hits winning, but segments
+    // only come from the index cells along the ray, and the walk stops once the hit
+    // so far is nearer than the next cell or the next cell is out of `range`.
+    pub fn find_nearest(&mut self, world: World, range: f32) {
+        // The winning obstacle's place in `World::obstacles`, the distance to its hit
+        // and the distance to the nearest hit of any obstacle so far.
+        let mut nearest: Option<(usize, f32, f32)> = None;
+        let segments = world.boundaries.len();
+
+        // Circles and doors are few, and testing them first can end the walk sooner.
+        let others = world
+            .circles
+            .iter()
+            .map(Obstacle::Circle)
+            .chain(world.doors.iter().map(Obstacle::Door));
+        for (i, obstacle) in others.enumerate() {
+            self.test_obstacle(obstacle, segments + i, &mut nearest);
+        }
+
+        let lookup = Ray::from_origin(self.origin, self.dir);
+        world.index.walk(&lookup, |cell, exit| {
+            for &i in cell {
+                let boundary = &world.boundaries[i];
+                if world.shows(boundary) {
+                    self.test_obstacle(Obstacle::Segment(boundary), i, &mut nearest);
+                }
+            }
+
+            exit < range && nearest.is_none_or(|(_, _, closest)| closest + CORNER_EPSILON >= exit)
+        });
+    }
+
+    pub fn test_obstacle(
+        &mut self,
+        obstacle: Obstacle,
+        order: usize,
+        nearest: &mut Option<(usize, f32, f32)>,
+    ) {
+        let new_hit = match obstacle {
+            Obstacle::Segment(boundary) => self
+                .intersect(boundary)
+                .map(|hit| (hit, Some(hit.k / boundary.length))),
+            Obstacle::Circle(circle) => self.intersect_circle(circle).map(|hit| (hit, None)),
+            // The door slides back along itself, so its texture slides with it.
+            Obstacle::Door(door) => {
+                self.intersect_segment(&door.boundary, door.length())
+                    .map(|hit| {
+                        let slide = door.open_amount * door.boundary.length;
+                        (hit, Some((hit.k + slide) / door.boundary.length))
+                    })
+            }
+        };
+
+        // Hits within `CORNER_EPSILON` of the closest one tie, and the lowest order
+        // wins. The window is measured from the closest hit rather than the current
+        // winner, so a run of near ties can't creep further away one step at a time.
+        if let Some((hit, offset)) = new_hit {
+            let distance = hit.t;
+            let closest = nearest.map_or(distance, |(_, _, closest)| closest.min(distance));
+            let better = nearest.is_none_or(|(best_order, best, _)| {
+                distance <= closest + CORNER_EPSILON
+                    && (order < best_order || best > closest + CORNER_EPSILON)
+            });
+
+            match nearest {
+                Some((_, _, nearest_closest)) if !better => *nearest_closest = closest,
+                _ => {
+                    self.set_hit(&obstacle, hit, offset);
+                    *nearest = Some((order, distance, closest));
+                }
+            }
+        }
+    }
+
+    // Also returns the perpendicular depth from the DDA, which `view` uses for the
+    // column height as is.
+    pub fn cast_grid(
+        player: &Player,
+        d_theta: f32,
+        grid: &Grid,
+        max_distance: f32,
+    ) -> (Ray, Option<f32>) {
+        let mut ray = Ray::new(player, d_theta);
+        let hit = grid.cast_dda(&ray, player.look_dir, max_distance);
+
+        if let Some((point, _, side, _tile)) = hit {
+            let distance = point.distance(ray.origin);
+
+            ray.end = Some(point);
+            ray.length = Some(distance);
+            ray.luminosity = Some(luminosity(distance));
+            ray.normal = Some(side.normal(ray.dir));
+            ray.side = Some(side);
+        }
+
+        (ray, hit.map(|(_, depth, _, _)| depth))
+    }
+
+    pub fn intersect(&self, boundary: &Boundary) -> Option<Hit> {
+        self.intersect_segment(boundary, boundary.length)
+    }
+
+    pub fn intersect_segment(&self, boundary: &Boundary, length: f32) -> Option<Hit> {
+        let determinant = (self.dir.x * boundary.dir.y) - (boundary.dir.x * self.dir.y);
+
+        // Both directions are unit length, so this is the sine of the angle between
+        // them; near zero the divisions below blow up to inf or NaN.
+        if !determinant.is_finite() || determinant.abs() < PARALLEL_EPSILON {
+            return None;
+        }
+
+        let k = (self.dir.x * (self.origin.y - boundary.origin.y))
+            - (self.dir.y * (self.origin.x - boundary.origin.x));
+
+        let lambda = (boundary.dir.x * (self.origin.y - boundary.origin.y))
+            - (boundary.dir.y * (self.origin.x - boundary.origin.x));
+
+        let k = k / determinant;
+        let lambda = lambda / determinant;
+
+        // Rounding could push a ray aimed exactly at a shared corner just off the end
+        // of both segments, leaving a seam, so both ends reach a little further.
+        // That can let both segments claim the corner; `test_obstacle` then treats
+        // the two hits as equally near, so the same one wins every frame.
+        if lambda >= 0.0 && length > 0.0 && (-CORNER_EPSILON..=length + CORNER_EPSILON).contains(&k)
+        {
+            let k = k.clamp(0.0, length);
+            let normal = boundary.dir.perp();
+
+            return Some(Hit {
+                point: boundary.origin + k * boundary.dir,
+                t: lambda,
+                k,
+                normal: if normal.dot(self.dir) > 0.0 {
+                    -normal
+                } else {
+                    normal
+                },
+            });
+        }
+
+        None
+    }
+
+    pub fn intersect_circle(&self, circle: &CircleBoundary) -> Option<Hit> {
+        let to_origin = self.origin - circle.center;
+        let b = self.dir.dot(to_origin);
+        let c = to_origin.length_squared() - circle.radius * circle.radius;
+        let discriminant = b * b - c;
+
+        if discriminant < 0.0 {
+            return None;
+        }
+
+        // From inside the circle the near root is behind the ray, so take the far one.
+        let sqrt = discriminant.sqrt();
+        let lambda = if -b - sqrt >= 0.0 {
+            -b - sqrt
+        } else {
+            -b + sqrt
+        };
+
+        if lambda < 0.0 {
+            return None;
+        }
+
+        let point = self.origin + lambda * self.dir;
+        let outward = (point - circle.center).normalize_or_zero();
+        let angle = outward.y.atan2(outward.x).rem_euclid(2.0 * PI);
+
+        Some(Hit {
+            point,
+            t: lambda,
+            k: angle * circle.radius,
+            normal: if outward.dot(self.dir) > 0.0 {
+                -outward
+            } else {
+                outward
+            },
+        })
+    }
+
+    pub fn set_hit(&mut self, obstacle: &Obstacle, hit: Hit, offset: Option<f32>) {
+        self.end = Some(hit.point);
+        self.length = Some(hit.t);
+        self.luminosity = Some(luminosity(hit.t));
+        self.offset = offset;
+        self.normal = Some(hit.normal);
+
+        match obstacle {
+            Obstacle::Segment(boundary) => {
+                self.texture = boundary.texture.clone();
+                self.color = boundary.color;
+                self.reflective = boundary.reflective;
+                self.height = boundary.height;
+                self.opacity = boundary.opacity;
+                self.material = boundary.material;
+                self.side = Some(boundary.side());
+            }
+            Obstacle::Circle(circle) => {
+                self.texture = None;
+                self.color = circle.color;
+                self.reflective = false;
+                self.height = 1.0;
+                self.opacity = 1.0;
+                self.material = Material::default();
+                self.side = None;
+            }
+            Obstacle::Door(door) => {
+                self.texture = door.boundary.texture.clone();
+                self.color = door.boundary.color;
+                self.reflective = false;
+                self.height = door.boundary.height;
+                self.opacity = door.boundary.opacity;
+                self.material = door.boundary.material;
+                self.side = Some(door.boundary.side());
+            }
+        }
+    }
+
+    pub fn show(&self, draw: &Draw, weight: f32, ambient: f32) {
+        // Near hits are bright blue and fade towards navy with distance; misses are a
+        // faint grey so they don't read as hits.
+        let color = match self.luminosity {
+            Some(luminosity) if self.end.is_some() => {
+                let t = shade(luminosity + ambient, 1.0);
+                rgba(0.4 * t, 0.6 * t, 0.25 + 0.75 * t, 1.0)
+            }
+            _ => rgba(0.5, 0.5, 0.5, 0.25),
+        };
+
+        let mut start = self.origin;
+        let mut dir = self.dir;
+
+        for &(bounce, next_start, bounce_dir) in &self.bounces {
+            draw.line()
+                .start(start)
+                .end(bounce)
+                .weight(weight)
+                .color(color);
+
+            start = next_start;
+            dir = bounce_dir;
+        }
+
+        match self.end {
+            Some(point) => {
+                draw.line()
+                    .start(start)
+                    .end(point)
+                    .weight(weight)
+                    .color(color);
+            }
+            None => {
+                draw.line()
+                    .start(start)
+                    .end(start + 1000.0 * dir)
+                    .weight(weight)
+                    .color(color);
+            }
+        }
+    }
+}
+
+impl Boundary {
+    pub fn new(start: Point2, end: Point2) -> Boundary {
+        Boundary {
+            origin: start,
+            dir: (end - start).normalize(),
+            length: (end - start).length(),
+            texture: None,
+            texture_path: None,
+            color: rgb(1.0, 1.0, 1.0),
+            reflective: false,
+            window_edge: false,
+            height: 1.0,
+            opacity: 1.0,
+            motion: None,
+            material: Material::default(),
+            solid: true,
+            layer: 0,
+        }
+    }
+
+    pub fn new_colored(start: Point2, end: Point2, color: Rgb) -> Boundary {
+        Boundary {
+            color,
+            ..Boundary::new(start, end)
+        }
+    }
+
+    // The line `MapFile::load` reads back as this boundary. Moving walls are written
+    // at their rest position.
+    pub fn to_map_line(&self) -> String {
+        let start = self
+            .motion
+            .as_ref()
+            .map_or(self.origin, |motion| motion.rest);
+        let end = start + self.length * self.dir;
+        let mut line = format!("{},{} {},{}", start.x, start.y, end.x, end.y);
+
+        if self.color != rgb(1.0, 1.0, 1.0) {
+            line += &format!(
+                " {},{},{}",
+                self.color.red, self.color.green, self.color.blue
+            );
+        }
+        if let Some(texture_path) = &self.texture_path {
+            line += &format!(" {}", texture_path);
+        }
+        if self.reflective {
+            line += " mirror";
+        }
+        if !self.solid {
+            line += " passable";
+        }
+        if self.layer != 0 {
+            line += &format!(" layer={}", self.layer);
+        }
+        if self.height != 1.0 {
+            line += &format!(" height={}", self.height);
+        }
+        if self.opacity != 1.0 {
+            line += &format!(" opacity={}", self.opacity);
+        }
+        if let Some(motion) = &self.motion {
+            line += &format!(
+                " move={},{},{}",
+                motion.offset.x, motion.offset.y, motion.period
+            );
+        }
+        if self.material.albedo != default_albedo() {
+            let albedo = self.material.albedo;
+            line += &format!(" albedo={},{},{}", albedo.red, albedo.green, albedo.blue);
+        }
+        if self.material.ambient != 0.0 {
+            line += &format!(" ambient={}", self.material.ambient);
+        }
+        if self.material.specular != 0.0 {
+            line += &format!(" specular={}", self.material.specular);
+        }
+
+        line
+    }
+
+    // Returns whether the boundary moved, so callers know to rebuild the index.
+    pub fn animate(&mut self, time: f32) -> bool {
+        match &self.motion {
+            Some(motion) => {
+                let t = 0.5 - 0.5 * (TAU * time / motion.period).cos();
+                self.origin = motion.rest + motion.offset * t;
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Always left, bottom, right, top, in that order. The left and bottom walls
+    // start at the bottom left corner and run up and right; the right and top walls
+    // start at the top right corner and run down and left, so each pair runs away
+    // from its shared corner. Texture offsets run along those directions, so
+    // changing them flips how textures land on the walls.
+    pub fn from_rect(rect: Rect) -> Vec<Boundary> {
+        vec![
+            Boundary::new(
+                pt2(rect.x.start, rect.y.start),
+                pt2(rect.x.start, rect.y.end),
+            ),
+            Boundary::new(
+                pt2(rect.x.start, rect.y.start),
+                pt2(rect.x.end, rect.y.start),
+            ),
+            Boundary::new(pt2(rect.x.end, rect.y.end), pt2(rect.x.end, rect.y.start)),
+            Boundary::new(pt2(rect.x.end, rect.y.end), pt2(rect.x.start, rect.y.end)),
+        ]
+    }
+
+    // Walls running closer to east-west face north or south, and vice versa.
+    pub fn side(&self) -> WallSide {
+        if self.dir.x.abs() >= self.dir.y.abs() {
+            WallSide::NorthSouth
+        } else {
+            WallSide::EastWest
+        }
+    }
+
+    // Joins `points` into a closed loop, including the edge from the last point back
+    // to the first. Repeated points are skipped rather than making zero-length walls.
+    pub fn from_polygon(points: &[Point2]) -> Vec<Boundary> {
+        points
+            .iter()
+            .zip(points.iter().cycle().skip(1))
+            .filter(|(a, b)| a.distance_squared(**b) > 0.0)
+            .map(|(&a, &b)| Boundary::new(a, b))
+            .collect()
+    }
+
+    pub fn window_edges(rect: Rect) -> Vec<Boundary> {
+        let mut return_val = Boundary::from_rect(rect);
+
+        for boundary in &mut return_val {
+            boundary.window_edge = true;
+        }
+
+        return_val
+    }
+
+    // One wall per line as `x1,y1 x2,y2 [r,g,b] [texture.png] [mirror]`, with colour
+    // channels in 0..1, optionally followed by `key=value` settings such as
+    // `specular=0.8` (see `parse_boundary`). A `polygon x,y x,y x,y ...` line adds the
+    // closed loop through its points. Blank lines and `#` comments are ignored.
+    pub fn from_map(path: &str, contents: &str) -> io::Result<Vec<Boundary>> {
+        let mut return_val: Vec<Boundary> = Vec::new();
+        let mut textures: HashMap<&str, Arc<RgbaImage>> = HashMap::new();
+
+        for (index, line) in config_lines(contents) {
+            if ["circle ", "door ", "light ", "portal ", "spawn "]
+                .iter()
+                .any(|prefix| line.starts_with(prefix))
+            {
+                continue;
+            }
+
+            if let Some(fields) = line.strip_prefix("polygon ") {
+                let points = fields
+                    .split_whitespace()
+                    .map(parse_point)
+                    .collect::<Result<Vec<Point2>, String>>()
+                    .map_err(|err| parse_error(path, index, &err))?;
+
+                if points.len() < 3 {
+                    return Err(parse_error(
+                        path,
+                        index,
+                        &format!("expected at least three points in `{}`", line),
+                    ));
+                }
+
+                return_val.extend(Boundary::from_polygon(&points));
+                continue;
+            }
+
+            let boundary = parse_boundary(path, index, line, &mut textures)?;
+            return_val.push(boundary);
+        }
+
+        Ok(return_val)
+    }
+
+    pub fn closest_point(&self, point: Point2) -> Point2 {
+        let k = (point - self.origin).dot(self.dir).clamp(0.0, self.length);
+        self.origin + k * self.dir
+    }
+
+    pub fn show(&self, draw: &Draw, weight: f32) {
+        draw.line()
+            .start(self.origin)
+            .end(self.origin + self.length * self.dir)
+            .weight(weight)
+            .color(self.color);
+    }
+}
+
+impl CircleBoundary {
+    pub fn new(center: Point2, radius: f32) -> CircleBoundary {
+        CircleBoundary {
+            center,
+            radius,
+            color: rgb(1.0, 1.0, 1.0),
+        }
+    }
+
+    // Circles share the map file with the wall segments as `circle x,y radius [r,g,b]`.
+    pub fn from_map(path: &str, contents: &str) -> io::Result<Vec<CircleBoundary>> {
+        let mut return_val: Vec<CircleBoundary> = Vec::new();
+
+        for (index, line) in config_lines(contents) {
+            let fields: Vec<&str> = match line.strip_prefix("circle ") {
+                Some(fields) => fields.split_whitespace().collect(),
+                None => continue,
+            };
+
+            if fields.len() != 2 && fields.len() != 3 {
+                return Err(parse_error(
+                    path,
+                    index,
+                    &format!("expected `circle x,y radius [r,g,b]`, found `{}`", line),
+                ));
+            }
+
+            let center = parse_point(fields[0]).map_err(|msg| parse_error(path, index, &msg))?;
+            let radius = fields[1].parse::<f32>().map_err(|_| {
+                parse_error(path, index, &format!("invalid radius `{}`", fields[1]))
+            })?;
+
+            let mut circle = CircleBoundary::new(center, radius);
+
+            if let Some(color) = fields.get(2) {
+                circle.color = parse_color(color).map_err(|msg| parse_error(path, index, &msg))?;
+            }
+
+            return_val.push(circle);
+        }
+
+        Ok(return_val)
+    }
+
+    pub fn show(&self, draw: &Draw, weight: f32) {
+        draw.ellipse()
+            .xy(self.center)
+            .radius(self.radius)
+            .no_fill()
+            .stroke_weight(weight)
+            .stroke(self.color);
+    }
+}
+
+impl<'a> Obstacle<'a> {
+    pub fn is_solid(&self) -> bool {
+        match self {
+            Obstacle::Segment(boundary) => boundary.solid,
+            Obstacle::Circle(_) | Obstacle::Door(_) => true,
+        }
+    }
+
+    pub fn closest_point(&self, point: Point2) -> Point2 {
+        match self {
+            Obstacle::Segment(boundary) => boundary.closest_point(point),
+            Obstacle::Circle(circle) => {
+                let dir = (point - circle.center).normalize_or_zero();
+                let dir = if dir == Vec2::ZERO {
+                    vec2(1.0, 0.0)
+                } else {
+                    dir
+                };
+                circle.center + circle.radius * dir
+            }
+            Obstacle::Door(door) => door.closest_point(point, door.length()),
+        }
+    }
+
+    // Unoriented: callers flip it to face whichever side they approach from.
+    pub fn normal_at(&self, point: Point2) -> Vec2 {
+        match self {
+            Obstacle::Segment(boundary) => boundary.dir.perp(),
+            Obstacle::Circle(circle) => (point - circle.center).normalize_or_zero(),
+            Obstacle::Door(door) => door.boundary.dir.perp(),
+        }
+    }
+}
+
+impl<'a> World<'a> {
+    pub fn shows(self, boundary: &Boundary) -> bool {
+        !self.hidden_layers.contains(&boundary.layer)
+    }
+
+    pub fn obstacles(self) -> impl Iterator<Item = Obstacle<'a>> {
+        self.boundaries
+            .iter()
+            .filter(move |boundary| self.shows(boundary))
+            .map(Obstacle::Segment)
+            .chain(self.circles.iter().map(Obstacle::Circle))
+            .chain(self.doors.iter().map(Obstacle::Door))
+    }
+
+    // The portal side a move from `start` to `end` passes through, as (entry, exit).
+    pub fn portal_crossed(
+        self,
+        start: Point2,
+        end: Point2,
+    ) -> Option<(&'a Boundary, &'a Boundary)> {
+        let travel = end - start;
+        let probe = Ray::from_origin(start, travel.normalize_or_zero());
+
+        self.portals
+            .iter()
+            .flat_map(|portal| portal.sides())
+            .find(|(entry, _)| {
+                probe
+                    .intersect(entry)
+                    .is_some_and(|hit| hit.t <= travel.length())
+            })
+    }
+}
+
+impl Portal {
+    // Portals share the map file as `portal x1,y1 x2,y2 x3,y3 x4,y4`, the first
+    // pair of points being one side and the second pair the other. Listing the
+    // sides in opposite directions walks straight through; the same direction
+    // turns you around.
+    pub fn from_map(path: &str, contents: &str) -> io::Result<Vec<Portal>> {
+        let mut return_val: Vec<Portal> = Vec::new();
+
+        for (index, line) in config_lines(contents) {
+            let fields: Vec<&str> = match line.strip_prefix("portal ") {
+                Some(fields) => fields.split_whitespace().collect(),
+                None => continue,
+            };
+
+            if fields.len() != 4 {
+                return Err(parse_error(
+                    path,
+                    index,
+                    &format!(
+                        "expected `portal x1,y1 x2,y2 x3,y3 x4,y4`, found `{}`",
+                        line
+                    ),
+                ));
+            }
+
+            let points = fields
+                .iter()
+                .map(|field| parse_point(field))
+                .collect::<Result<Vec<Point2>, String>>()
+                .map_err(|msg| parse_error(path, index, &msg))?;
+
+            return_val.push(Portal {
+                a: Boundary::new(points[0], points[1]),
+                b: Boundary::new(points[2], points[3]),
+            });
+        }
+
+        Ok(return_val)
+    }
+
+    pub fn sides(&self) -> [(&Boundary, &Boundary); 2] {
+        [(&self.a, &self.b), (&self.b, &self.a)]
+    }
+
+    pub fn show(&self, draw: &Draw, weight: f32) {
+        for side in [&self.a, &self.b] {
+            draw.line()
+                .start(side.origin)
+                .end(side.origin + side.length * side.dir)
+                .weight(weight)
+                .color(ORANGE);
+        }
+    }
+}
+
+// The rigid map from one portal side onto the other: the entry's start lands on
+// the exit's end and its front faces the exit's back, so things leave the way
+// they came in. Positions along the side are scaled if the lengths differ.
+pub fn portal_point(entry: &Boundary, exit: &Boundary, point: Point2) -> Point2 {
+    let relative = point - entry.origin;
+    let along = relative.dot(entry.dir) / entry.length * exit.length;
+    let across = relative.dot(entry.dir.perp());
+
+    exit.origin + exit.length * exit.dir - (along * exit.dir + across * exit.dir.perp())
+}
+
+pub fn portal_dir(entry: &Boundary, exit: &Boundary, dir: Vec2) -> Vec2 {
+    -(dir.dot(entry.dir) * exit.dir + dir.dot(entry.dir.perp()) * exit.dir.perp())
+}
+
+impl Door {
+    pub fn new(boundary: Boundary) -> Door {
+        Door {
+            boundary,
+            open_amount: 0.0,
+            state: DoorState::Closed,
+        }
+    }
+
+    pub fn is_moving(&self) -> bool {
+        matches!(self.state, DoorState::Opening | DoorState::Closing)
+    }
+
+    // Doors share the map file with the walls as `door x1,y1 x2,y2 [r,g,b] [texture]`.
+    pub fn from_map(path: &str, contents: &str) -> io::Result<Vec<Door>> {
+        let mut return_val: Vec<Door> = Vec::new();
+        let mut textures: HashMap<&str, Arc<RgbaImage>> = HashMap::new();
+
+        for (index, line) in config_lines(contents) {
+            if let Some(fields) = line.strip_prefix("door ") {
+                let boundary = parse_boundary(path, index, fields, &mut textures)?;
+                return_val.push(Door::new(boundary));
+            }
+        }
+
+        Ok(return_val)
+    }
+
+    // The door retracts toward its origin as it opens.
+    pub fn length(&self) -> f32 {
+        self.boundary.length * (1.0 - self.open_amount)
+    }
+
+    pub fn closest_point(&self, point: Point2, length: f32) -> Point2 {
+        let k = (point - self.boundary.origin)
+            .dot(self.boundary.dir)
+            .clamp(0.0, length);
+        self.boundary.origin + k * self.boundary.dir
+    }
+
+    pub fn toggle(&mut self) {
+        self.state = match self.state {
+            DoorState::Closed | DoorState::Closing => DoorState::Opening,
+            DoorState::Open | DoorState::Opening => DoorState::Closing,
+        };
+    }
+
+    pub fn update(&mut self, dt: f32, player: &Player) {
+        match self.state {
+            DoorState::Opening => {
+                self.open_amount = (self.open_amount + DOOR_SPEED * dt).min(1.0);
+
+                if self.open_amount == 1.0 {
+                    self.state = DoorState::Open;
+                }
+            }
+            DoorState::Closing => {
+                let open_amount = (self.open_amount - DOOR_SPEED * dt).max(0.0);
+                let length = self.boundary.length * (1.0 - open_amount);
+
+                // Back off rather than close onto the player.
+                if (player.pos - self.closest_point(player.pos, length)).length() < PLAYER_RADIUS {
+                    self.state = DoorState::Opening;
+                    return;
+                }
+
+                self.open_amount = open_amount;
+
+                if self.open_amount == 0.0 {
+                    self.state = DoorState::Closed;
+                }
+            }
+            DoorState::Closed | DoorState::Open => {}
+        }
+    }
+
+    pub fn show(&self, draw: &Draw, weight: f32) {
+        draw.line()
+            .start(self.boundary.origin)
+            .end(self.boundary.origin + self.length() * self.boundary.dir)
+            .weight(weight)
+            .color(self.boundary.color);
+    }
+}
+
+impl BoundaryIndex {
+    pub fn build(boundaries: &[Boundary]) -> BoundaryIndex {
+        let points = boundaries.iter().flat_map(|boundary| {
+            [
+                boundary.origin,
+                boundary.origin + boundary.length * boundary.dir,
+            ]
+        });
+
+        let (min, max) = points.fold(
+            (pt2(f32::MAX, f32::MAX), pt2(f32::MIN, f32::MIN)),
+            |(min, max), point| (min.min(point), max.max(point)),
+        );
+
+        let mut index = BoundaryIndex {
+            origin: min,
+            cell_size: INDEX_CELL_SIZE,
+            cols: 0,
+            rows: 0,
+            cells: Vec::new(),
+        };
+
+        if boundaries.is_empty() {
+            return index;
+        }
+
+        index.cols = ((max.x - min.x) / INDEX_CELL_SIZE).floor() as i32 + 1;
+        index.rows = ((max.y - min.y) / INDEX_CELL_SIZE).floor() as i32 + 1;
+        index.cells = vec![Vec::new(); (index.cols * index.rows) as usize];
+
+        for (i, boundary) in boundaries.iter().enumerate() {
+            let end = boundary.origin + boundary.length * boundary.dir;
+
+            // Padding the bounding box keeps hits that land exactly on a cell edge
+            // registered on both sides of it.
+            let (min_col, min_row) = index.cell_coords(boundary.origin.min(end) - vec2(0.01, 0.01));
+            let (max_col, max_row) = index.cell_coords(boundary.origin.max(end) + vec2(0.01, 0.01));
+
+            for row in min_row.max(0)..=max_row.min(index.rows - 1) {
+                for col in min_col.max(0)..=max_col.min(index.cols - 1) {
+                    index.cells[(row * index.cols + col) as usize].push(i);
+                }
+            }
+        }
+
+        index
+    }
+
+    pub fn cell_coords(&self, point: Point2) -> (i32, i32) {
+        (
+            ((point.x - self.origin.x) / self.cell_size).floor() as i32,
+            ((point.y - self.origin.y) / self.cell_size).floor() as i32,
+        )
+    }
+
+    // Visits the cells the ray passes through, nearest first, with the distance
+    // along the ray at which it leaves each one, until `visit` returns false. A
+    // boundary spanning several cells is visited once for each.
+    pub fn walk(&self, ray: &Ray, mut visit: impl FnMut(&[usize], f32) -> bool) {
+        if let Some(t) = self.entry_distance(ray) {
+            let entry = ray.origin + t * ray.dir;
+            let (mut col, mut row) = self.cell_coords(entry);
+            col = col.clamp(0, self.cols - 1);
+            row = row.clamp(0, self.rows - 1);
+
+            let step_col = if ray.dir.x < 0.0 { -1 } else { 1 };
+            let step_row = if ray.dir.y < 0.0 { -1 } else { 1 };
+            let delta = vec2(
+                (self.cell_size / ray.dir.x).abs(),
+                (self.cell_size / ray.dir.y).abs(),
+            );
+
+            let cell_min = self.origin + vec2(col as f32, row as f32) * self.cell_size;
+            let mut side_dist = vec2(
+                if ray.dir.x < 0.0 {
+                    (entry.x - cell_min.x) / -ray.dir.x
+                } else {
+                    (cell_min.x + self.cell_size - entry.x) / ray.dir.x
+                },
+                if ray.dir.y < 0.0 {
+                    (entry.y - cell_min.y) / -ray.dir.y
+                } else {
+                    (cell_min.y + self.cell_size - entry.y) / ray.dir.y
+                },
+            );
+
+            while col >= 0 && row >= 0 && col < self.cols && row < self.rows {
+                let exit = t + side_dist.x.min(side_dist.y);
+
+                if !visit(&self.cells[(row * self.cols + col) as usize], exit) {
+                    return;
+                }
+
+                if side_dist.x < side_dist.y {
+                    side_dist.x += delta.x;
+                    col += step_col;
+                } else {
+                    side_dist.y += delta.y;
+                    row += step_row;
+                }
+            }
+        }
+    }
+
+    pub fn entry_distance(&self, ray: &Ray) -> Option<f32> {
+        if self.cells.is_empty() {
+            return None;
+        }
+
+        let min = self.origin;
+        let max = self.origin + vec2(self.cols as f32, self.rows as f32) * self.cell_size;
+
+        let mut t_enter = 0.0f32;
+        let mut t_exit = f32::MAX;
+
+        for (origin, dir, min, max) in [
+            (ray.origin.x, ray.dir.x, min.x, max.x),
+            (ray.origin.y, ray.dir.y, min.y, max.y),
+        ] {
+            if dir == 0.0 {
+                if origin < min || origin > max {
+                    return None;
+                }
+            } else {
+                let t0 = (min - origin) / dir;
+                let t1 = (max - origin) / dir;
+                t_enter = t_enter.max(t0.min(t1));
+                t_exit = t_exit.min(t0.max(t1));
+            }
+        }
+
+        if t_enter > t_exit {
+            return None;
+        }
+
+        Some(t_enter)
+    }
+}
+
+impl Grid {
+    pub fn from_rect(rect: Rect, cell_size: f32) -> Grid {
+        let cols = (rect.w() / cell_size).ceil() as usize;
+        let rows = (rect.h() / cell_size).ceil() as usize;
+
+        let cells = (0..rows)
+            .map(|row| {
+                (0..cols)
+                    .map(|col| {
+                        let edge = row == 0 || row == rows - 1 || col == 0 || col == cols - 1;
+                        edge as u8
+                    })
+                    .collect()
+            })
+            .collect();
+
+        Grid { cells, cell_size }
+    }
+
+    // Dark pixels are walls and light ones are empty. Coloured walls keep their
+    // dominant channel as the tile value (2 red, 3 green, 4 blue) so they can be
+    // told apart later; grey and black walls are 1.
+    pub fn from_image(path: &str) -> io::Result<Grid> {
+        let image = image::open(path)
+            .map_err(|err| match err {
+                image::ImageError::IoError(err) => err,
+                err => io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path, err)),
+            })?
+            .to_rgb8();
+
+        let cells = image
+            .rows()
+            .map(|row| {
+                row.map(|pixel| {
+                    let [r, g, b] = pixel.0.map(|channel| channel as f32 / 255.0);
+
+                    if (r + g + b) / 3.0 >= 0.5 {
+                        0
+                    } else if r.max(g).max(b) - r.min(g).min(b) < 0.2 {
+                        1
+                    } else if r >= g && r >= b {
+                        2
+                    } else if g >= b {
+                        3
+                    } else {
+                        4
+                    }
+                })
+                .collect()
+            })
+            .collect();
+
+        Ok(Grid {
+            cells,
+            cell_size: GRID_CELL_SIZE,
+        })
+    }
+
+    pub fn rows(&self) -> i32 {
+        self.cells.len() as i32
+    }
+
+    pub fn cols(&self) -> i32 {
+        self.cells.first().map_or(0, |row| row.len()) as i32
+    }
+
+    // The grid is centred on the world origin with row 0 at the top.
+    pub fn top_left(&self) -> Point2 {
+        pt2(
+            -(self.cols() as f32 * self.cell_size) / 2.0,
+            (self.rows() as f32 * self.cell_size) / 2.0,
+        )
+    }
+
+    pub fn tile(&self, col: i32, row: i32) -> Option<u8> {
+        if col < 0 || row < 0 || col >= self.cols() || row >= self.rows() {
+            return None;
+        }
+
+        Some(self.cells[row as usize][col as usize])
+    }
+
+    // Returns the hit point, its distance perpendicular to the camera plane, the side
+    // that was hit and the tile value. As in classic raycasters the ray direction is
+    // scaled to reach the camera plane at 1, so the DDA steps measure that distance
+    // directly and a flat wall comes out at a flat depth. `look_dir` must be within
+    // 90° of the ray. The walk gives up once it is `max_distance` along the ray.
+    pub fn cast_dda(
+        &self,
+        ray: &Ray,
+        look_dir: Vec2,
+        max_distance: f32,
+    ) -> Option<(Point2, f32, WallSide, u8)> {
+        let top_left = self.top_left();
+        let start = vec2(
+            (ray.origin.x - top_left.x) / self.cell_size,
+            (top_left.y - ray.origin.y) / self.cell_size,
+        );
+        let forward = ray.dir.dot(look_dir);
+
+        if forward <= 0.0 {
+            return None;
+        }
+
+        let world_dir = ray.dir / forward;
+        let dir = vec2(world_dir.x, -world_dir.y);
+
+        let mut col = start.x.floor() as i32;
+        let mut row = start.y.floor() as i32;
+        let step_col = if dir.x < 0.0 { -1 } else { 1 };
+        let step_row = if dir.y < 0.0 { -1 } else { 1 };
+
+        let delta = vec2((1.0 / dir.x).abs(), (1.0 / dir.y).abs());
+        let mut side_dist = vec2(
+            if dir.x < 0.0 {
+                (start.x - col as f32) * delta.x
+            } else {
+                (col as f32 + 1.0 - start.x) * delta.x
+            },
+            if dir.y < 0.0 {
+                (start.y - row as f32) * delta.y
+            } else {
+                (row as f32 + 1.0 - start.y) * delta.y
+            },
+        );
+
+        loop {
+            let (distance, side) = if side_dist.x < side_dist.y {
+                col += step_col;
+                side_dist.x += delta.x;
+                (side_dist.x - delta.x, WallSide::EastWest)
+            } else {
+                row += step_row;
+                side_dist.y += delta.y;
+                (side_dist.y - delta.y, WallSide::NorthSouth)
+            };
+
+            if distance * self.cell_size * world_dir.length() > max_distance {
+                return None;
+            }
+
+            match self.tile(col, row) {
+                Some(0) => {}
+                Some(tile) => {
+                    let depth = distance * self.cell_size;
+                    return Some((ray.origin + depth * world_dir, depth, side, tile));
+                }
+                None => {
+                    let leaving_cols =
+                        (col < 0 && step_col < 0) || (col >= self.cols() && step_col > 0);
+                    let leaving_rows =
+                        (row < 0 && step_row < 0) || (row >= self.rows() && step_row > 0);
+
+                    if leaving_cols || leaving_rows {
+                        return None;
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn show(&self, draw: &Draw) {
+        let top_left = self.top_left();
+
+        for row in 0..self.rows() {
+            for col in 0..self.cols() {
+                if self.tile(col, row) != Some(0) {
+                    draw.rect()
+                        .x_y(
+                            top_left.x + (col as f32 + 0.5) * self.cell_size,
+                            top_left.y - (row as f32 + 0.5) * self.cell_size,
+                        )
+                        .w_h(self.cell_size, self.cell_size)
+                        .color(GRAY);
+                }
+            }
+        }
+    }
+}
+
+// Light from the player's lamp, falling off with distance. The scene's ambient
+// light is added on top when shading.
+pub fn luminosity(distance: f32) -> f32 {
+    5000.0 / ((distance / 5.0) * (distance / 5.0))
+}
+
+/// Distance from the eye to a flat screen `width` pixels wide that spans `fov`
+/// degrees, so a world length at depth d projects to `length * distance / d` pixels.
+pub fn projection_distance(fov: f32, width: f32) -> f32 {
+    (width / 2.0) / (fov.to_radians() / 2.0).tan()
+}
+
+/// On-screen height of a full-height wall at `depth`, with the depth clamped to
+/// `near_clip` first so it stays finite however close the wall is.
+pub fn projected_wall_height(depth: f32, projection: f32, near_clip: f32) -> f32 {
+    WALL_HEIGHT * projection / depth.max(near_clip)
+}
+
+/// Maps raw luminosity into 0..1 with a soft knee instead of a hard clamp, so close
+/// walls ease towards full brightness rather than snapping to it.
+pub fn shade(luminosity: f32, gamma: f32) -> f32 {
+    (luminosity / (1.0 + luminosity)).powf(gamma)
+}
+
+pub fn config_lines(contents: &str) -> impl Iterator<Item = (usize, &str)> {
+    contents
+        .lines()
+        .enumerate()
+        .map(|(index, line)| match line.find('#') {
+            Some(comment) => (index, line[..comment].trim()),
+            None => (index, line.trim()),
+        })
+        .filter(|(_, line)| !line.is_empty())
+}
+
+pub fn parse_point(text: &str) -> Result<Point2, String> {
+    let (x, y) = text
+        .split_once(',')
+        .ok_or_else(|| format!("expected a point `x,y`, found `{}`", text))?;
+
+    let x = x
+        .trim()
+        .parse::<f32>()
+        .map_err(|_| format!("invalid x coordinate `{}`", x))?;
+    let y = y
+        .trim()
+        .parse::<f32>()
+        .map_err(|_| format!("invalid y coordinate `{}`", y))?;
+
+    Ok(pt2(x, y))
+}
+
+pub fn parse_boundary<'a>(
+    path: &str,
+    index: usize,
+    line: &'a str,
+    textures: &mut HashMap<&'a str, Arc<RgbaImage>>,
+) -> io::Result<Boundary> {
+    let points: Vec<&str> = line.split_whitespace().collect();
+    if points.len() < 2 || points.len() > 13 {
+        return Err(parse_error(
+            path,
+            index,
+            &format!(
+                "expected `x1,y1 x2,y2 [r,g,b] [texture] [mirror] [passable] [layer=n] [height=h] [opacity=a] [move=dx,dy,period] [albedo=r,g,b] [ambient=a] [specular=s]`, found `{}`",
+                line
+            ),
+        ));
+    }
+
+    let start = parse_point(points[0]).map_err(|msg| parse_error(path, index, &msg))?;
+    let end = parse_point(points[1]).map_err(|msg| parse_error(path, index, &msg))?;
+    // A wall with no length has no direction to cast against.
+    if start == end {
+        return Err(parse_error(
+            path,
+            index,
+            &format!("wall `{}` starts and ends at the same point", line),
+        ));
+    }
+
+    let mut color = None;
+    let mut texture = None;
+    let mut texture_path = None;
+    let mut reflective = false;
+    let mut solid = true;
+    let mut layer = 0;
+    let mut height = 1.0;
+    let mut opacity = 1.0;
+    let mut motion = None;
+    let mut material = Material::default();
+
+    for &extra in &points[2..] {
+        if extra == "mirror" {
+            reflective = true;
+        } else if extra == "passable" {
+            solid = false;
+        } else if let Some(value) = extra.strip_prefix("layer=") {
+            layer = value
+                .parse::<u32>()
+                .map_err(|_| parse_error(path, index, &format!("invalid layer `{}`", value)))?;
+        } else if let Some(value) = extra.strip_prefix("height=") {
+            height = value
+                .parse::<f32>()
+                .map_err(|_| parse_error(path, index, &format!("invalid height `{}`", value)))?;
+        } else if let Some(value) = extra.strip_prefix("opacity=") {
+            opacity = value
+                .parse::<f32>()
+                .map_err(|_| parse_error(path, index, &format!("invalid opacity `{}`", value)))?
+                .clamp(0.0, 1.0);
+        } else if let Some(value) = extra.strip_prefix("albedo=") {
+            material.albedo = parse_color(value).map_err(|msg| parse_error(path, index, &msg))?;
+        } else if let Some(value) = extra.strip_prefix("ambient=") {
+            material.ambient = value
+                .parse::<f32>()
+                .map_err(|_| parse_error(path, index, &format!("invalid ambient `{}`", value)))?;
+        } else if let Some(value) = extra.strip_prefix("specular=") {
+            material.specular = value
+                .parse::<f32>()
+                .map_err(|_| parse_error(path, index, &format!("invalid specular `{}`", value)))?;
+        } else if let Some(value) = extra.strip_prefix("move=") {
+            let fields = value
+                .split(',')
+                .map(|field| field.trim().parse::<f32>())
+                .collect::<Result<Vec<f32>, _>>();
+
+            match fields.as_deref() {
+                Ok(&[dx, dy, period]) if period > 0.0 => {
+                    motion = Some(BoundaryMotion {
+                        rest: start,
+                        offset: vec2(dx, dy),
+                        period,
+                    })
+                }
+                _ => {
+                    return Err(parse_error(
+                        path,
+                        index,
+                        &format!("expected `move=dx,dy,period`, found `{}`", extra),
+                    ))
+                }
+            }
+        } else if extra.contains(',') {
+            color = Some(parse_color(extra).map_err(|msg| parse_error(path, index, &msg))?);
+        } else {
+            if !textures.contains_key(extra) {
+                let image = load_texture(extra).map_err(|msg| parse_error(path, index, &msg))?;
+                textures.insert(extra, Arc::new(image));
+            }
+            texture = textures.get(extra).cloned();
+            texture_path = Some(extra.to_string());
+        }
+    }
+
+    let mut boundary = match color {
+        Some(color) => Boundary::new_colored(start, end, color),
+        None => Boundary::new(start, end),
+    };
+    boundary.texture = texture;
+    boundary.texture_path = texture_path;
+    boundary.reflective = reflective;
+    boundary.height = height;
+    boundary.opacity = opacity;
+    boundary.motion = motion;
+    boundary.material = material;
+    boundary.solid = solid;
+    boundary.layer = layer;
+
+    Ok(boundary)
+}
+
+pub fn parse_color(text: &str) -> Result<Rgb, String> {
+    let channels = text
+        .split(',')
+        .map(|channel| channel.trim().parse::<f32>())
+        .collect::<Result<Vec<f32>, _>>()
+        .map_err(|_| format!("invalid colour `{}`", text))?;
+
+    match channels[..] {
+        [red, green, blue] => Ok(rgb(red, green, blue)),
+        _ => Err(format!("expected a colour `r,g,b`, found `{}`", text)),
+    }
+}
+
+// An image with no pixels has no texel to sample, so it's turned away here rather
+// than at draw time.
+pub fn load_texture(path: &str) -> Result<RgbaImage, String> {
+    let image = image::open(path).map_err(|err| err.to_string())?.to_rgba8();
+
+    if image.width() == 0 || image.height() == 0 {
+        return Err(format!("texture `{}` has no pixels", path));
+    }
+
+    Ok(image)
+}
+
+pub fn parse_error(path: &str, index: usize, msg: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("{}:{}: {}", path, index + 1, msg),
+    )
+}
+
+/// What a single screen column sees, independent of how it ends up drawn.
+pub struct ColumnResult {
+    pub column: i32,
+    pub length: Option<f32>,
+    pub luminosity: Option<f32>,
+    /// Distance along the look direction, used for wall height and sprite occlusion.
+    pub depth: f32,
+    pub ray: Ray,
+}
+
+/// The angle off the look direction for every screen column. It only depends on
+/// the FOV, resolution, window width and column offset, so it's rebuilt only when
+/// one changes.
+pub struct ColumnAngles {
+    pub fov: f32,
+    pub resolution: i32,
+    pub width: f32,
+    /// How far the columns are shifted right, as a fraction of a column in 0..1.
+    pub offset: f32,
+    pub columns: Vec<(i32, f32)>,
+}
+
+impl ColumnAngles {
+    pub fn new(fov: f32, resolution: i32, width: f32) -> ColumnAngles {
+        ColumnAngles::with_offset(fov, resolution, width, 0.0)
+    }
+
+    pub fn with_offset(fov: f32, resolution: i32, width: f32, offset: f32) -> ColumnAngles {
+        let half_width = width / 2.0;
+
+        // Round outwards so a resolution that doesn't divide the width still covers
+        // the edges; the overhang is simply clipped by the window. Shifted columns
+        // need one more on the left to close the gap they leave.
+        let half_columns = (half_width / resolution as f32).ceil() as i32;
+        let first = if offset > 0.0 {
+            -half_columns - 1
+        } else {
+            -half_columns
+        };
+
+        let mut angles = ColumnAngles {
+            fov,
+            resolution,
+            width,
+            offset,
+            columns: Vec::new(),
+        };
+
+        angles.columns = (first..half_columns)
+            .map(|column| (column, angles.angle_at(column as f32 + 0.5 + offset)))
+            .collect();
+
+        angles
+    }
+
+    /// The offset that pins the columns to fixed world angles for a player facing
+    /// `look_angle`, so turning slides them across the screen instead of sampling
+    /// the walls at new places every frame. Exact at the centre of the screen.
+    pub fn stable_offset(fov: f32, resolution: i32, width: f32, look_angle: f32) -> f32 {
+        let radians_per_column = resolution as f32 * (fov.to_radians() / 2.0).tan() / (width / 2.0);
+        (-look_angle / radians_per_column).rem_euclid(1.0)
+    }
+
+    /// The screen x of the left edge of `column`.
+    pub fn left_edge(&self, column: i32) -> f32 {
+        (column as f32 + self.offset) * self.resolution as f32
+    }
+
+    /// The column covering screen x `x`.
+    pub fn column_at(&self, x: f32) -> i32 {
+        (x / self.resolution as f32 - self.offset).floor() as i32
+    }
+
+    /// The ray angle `position` columns right of the screen centre, where column
+    /// `c` covers `c..c + 1`.
+    pub fn angle_at(&self, position: f32) -> f32 {
+        let tan_half_fov = (self.fov.to_radians() / 2.0).tan();
+
+        // Spacing the rays evenly across a flat screen (rather than evenly in angle)
+        // keeps straight walls straight once the distance is corrected in
+        // `cast_columns`.
+        let screen_x = position / (self.width / 2.0 / self.resolution as f32);
+        (screen_x * tan_half_fov).atan()
+    }
+
+    pub fn first_column(&self) -> i32 {
+        self.columns.first().map_or(0, |&(column, _)| column)
+    }
+
+    pub fn last_column(&self) -> i32 {
+        self.columns.last().map_or(0, |&(column, _)| column)
+    }
+
+    /// `count` rays spread evenly across the screen, the first through the centre
+    /// of the first column and the last through the centre of the last, so the
+    /// edge columns never fall outside them. They're numbered from 0 in place of
+    /// columns; only the angles mean anything, so they're cast without
+    /// antialiasing.
+    pub fn rays(&self, count: i32) -> ColumnAngles {
+        let first = self.first_column() as f32 + 0.5 + self.offset;
+        let span = (self.last_column() - self.first_column()) as f32;
+        let gaps = (count - 1).max(1) as f32;
+
+        ColumnAngles {
+            columns: (0..count)
+                .map(|ray| (ray, self.angle_at(first + span * ray as f32 / gaps)))
+                .collect(),
+            ..*self
+        }
+    }
+
+    pub fn update(&mut self, fov: f32, resolution: i32, width: f32, offset: f32) {
+        if self.fov != fov
+            || self.resolution != resolution
+            || self.width != width
+            || self.offset != offset
+        {
+            *self = ColumnAngles::with_offset(fov, resolution, width, offset);
+        }
+    }
+}
+
+/// Casts one ray per column, without touching nannou, so the ray math can be
+/// exercised headlessly. See `cast_columns` for `aa_samples`.
+pub fn cast_scene(
+    player: &Player,
+    world: World,
+    angles: &ColumnAngles,
+    max_bounces: u32,
+    max_distance: f32,
+    aa_samples: u32,
+) -> Vec<ColumnResult> {
+    cast_columns(player, angles, aa_samples, |angle| {
+        (
+            Ray::cast(player, angle, world, max_bounces, max_distance),
+            None,
+        )
+    })
+}
+
+pub fn cast_grid_scene(
+    player: &Player,
+    grid: &Grid,
+    angles: &ColumnAngles,
+    max_distance: f32,
+    aa_samples: u32,
+) -> Vec<ColumnResult> {
+    cast_columns(player, angles, aa_samples, |angle| {
+        Ray::cast_grid(player, angle, grid, max_distance)
+    })
+}
+
+// `cast` may return the perpendicular depth itself; otherwise it is projected from
+// the ray length.
+//
+// With more than one AA sample, that many extra rays are spread across each column
+// and the column takes their average height, brightness and colour, so a wall edge
+// that covers part of a column only partly raises it, even when the centre ray
+// misses. The centre ray decides what the column shows if it hits, otherwise the
+// nearest sample that does; one sample is exactly one ray per column.
+pub fn cast_columns(
+    player: &Player,
+    angles: &ColumnAngles,
+    aa_samples: u32,
+    cast: impl Fn(f32) -> (Ray, Option<f32>) + Sync,
+) -> Vec<ColumnResult> {
+    let depth_of = |ray: &Ray, depth: Option<f32>| match (depth, ray.length) {
+        (Some(depth), _) => depth,
+        (None, Some(length)) => length * ray.dir.dot(player.look_dir),
+        (None, None) => f32::INFINITY,
+    };
+
+    angles
+        .columns
+        .par_iter()
+        .map(|&(column, angle)| {
+            let (mut ray, depth) = cast(angle);
+            let mut depth = depth_of(&ray, depth);
+            let mut luminosity = ray.luminosity;
+
+            if aa_samples > 1 {
+                let samples: Vec<(Ray, f32)> = (0..aa_samples)
+                    .map(|sample| {
+                        let position = column as f32
+                            + angles.offset
+                            + (sample as f32 + 0.5) / aa_samples as f32;
+                        let (ray, depth) = cast(angles.angle_at(position));
+                        let depth = depth_of(&ray, depth);
+                        (ray, depth)
+                    })
+                    .collect();
+                let hits: Vec<&(Ray, f32)> = samples
+                    .iter()
+                    .filter(|(ray, _)| ray.length.is_some())
+                    .collect();
+
+                if ray.length.is_none() {
+                    if let Some((nearest, _)) =
+                        hits.iter().min_by(|(_, a), (_, b)| a.total_cmp(b)).copied()
+                    {
+                        ray = nearest.clone();
+                    }
+                }
+
+                if !hits.is_empty() {
+                    // Heights go as 1 / depth, so that is what gets averaged; a miss
+                    // adds no height at all.
+                    let inverse_depth: f32 = hits.iter().map(|(_, depth)| 1.0 / depth).sum();
+                    let light: f32 = hits
+                        .iter()
+                        .map(|(ray, _)| ray.luminosity.unwrap_or(0.0))
+                        .sum();
+                    let (red, green, blue) =
+                        hits.iter().fold((0.0, 0.0, 0.0), |(r, g, b), (ray, _)| {
+                            (r + ray.color.red, g + ray.color.green, b + ray.color.blue)
+                        });
+                    let count = hits.len() as f32;
+
+                    depth = aa_samples as f32 / inverse_depth;
+                    luminosity = Some(light / aa_samples as f32);
+                    ray.color = rgb(red / count, green / count, blue / count);
+                }
+            }
+
+            ColumnResult {
+                column,
+                length: ray.length,
+                luminosity,
+                depth,
+                ray,
+            }
+        })
+        .collect()
+}
+
+/// Stretches `rays`, cast with `angles.rays`, back over the columns of `angles`.
+/// Each column blends the two rays either side of it: heights through 1 / depth as
+/// with antialiasing, along with the brightness and colour. The rest of what the
+/// column shows, such as its texture and glass, comes from the nearer of the two,
+/// and the texture offset is only blended when both hit the same surface.
+pub fn interpolate_rays(angles: &ColumnAngles, rays: &[ColumnResult]) -> Vec<ColumnResult> {
+    if rays.is_empty() {
+        return Vec::new();
+    }
+
+    let first = angles.first_column();
+    let span = (angles.last_column() - first).max(1) as f32;
+    let last_ray = rays.len() - 1;
+
+    angles
+        .columns
+        .iter()
+        .map(|&(column, _)| {
+            let position = (column - first) as f32 / span * last_ray as f32;
+            let i = (position.floor() as usize).min(last_ray.saturating_sub(1));
+            let t = position - i as f32;
+            let (a, b) = (&rays[i], &rays[(i + 1).min(last_ray)]);
+            let nearest = if t < 0.5 { a } else { b };
+            let mut ray = nearest.ray.clone();
+
+            let (length, depth, luminosity) = match (a.length, b.length) {
+                (Some(a_length), Some(b_length)) => {
+                    let blend = |x: f32, y: f32| x + (y - x) * t;
+
+                    ray.color = rgb(
+                        blend(a.ray.color.red, b.ray.color.red),
+                        blend(a.ray.color.green, b.ray.color.green),
+                        blend(a.ray.color.blue, b.ray.color.blue),
+                    );
+                    ray.height = blend(a.ray.height, b.ray.height);
+                    if let (Some(a_end), Some(b_end)) = (a.ray.end, b.ray.end) {
+                        ray.end = Some(a_end.lerp(b_end, t));
+                    }
+
+                    let same_surface = a.ray.normal == b.ray.normal
+                        && match (&a.ray.texture, &b.ray.texture) {
+                            (Some(a_texture), Some(b_texture)) => Arc::ptr_eq(a_texture, b_texture),
+                            (None, None) => true,
+                            _ => false,
+                        };
+                    if let (true, Some(a_offset), Some(b_offset)) =
+                        (same_surface, a.ray.offset, b.ray.offset)
+                    {
+                        ray.offset = Some(blend(a_offset, b_offset));
+                    }
+
+                    let luminosity = match (a.luminosity, b.luminosity) {
+                        (Some(x), Some(y)) => Some(blend(x, y)),
+                        _ => nearest.luminosity,
+                    };
+
+                    (
+                        Some(blend(a_length, b_length)),
+                        1.0 / blend(1.0 / a.depth, 1.0 / b.depth),
+                        luminosity,
+                    )
+                }
+                // A miss has no height to blend towards, so the edge of an opening
+                // stays with whichever ray is closer.
+                _ => (nearest.length, nearest.depth, nearest.luminosity),
+            };
+            ray.length = length;
+
+            ColumnResult {
+                column,
+                length,
+                luminosity,
+                depth,
+                ray,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nannou::rand::rngs::StdRng;
+    use nannou::rand::{Rng, SeedableRng};
+
+    fn assert_close(actual: f32, expected: f32) {
+        assert!(
+            (actual - expected).abs() < 1e-3,
+            "expected {}, got {}",
+            expected,
+            actual
+        );
+    }
+
+    fn assert_points_close(actual: Point2, expected: Point2) {
+        assert_close(actual.x, expected.x);
+        assert_close(actual.y, expected.y);
+    }
+
+    // Runs `f` with a world holding just `boundaries`, nothing hidden.
+    fn with_world<T>(boundaries: &[Boundary], f: impl FnOnce(World) -> T) -> T {
+        let index = BoundaryIndex::build(boundaries);
+        let hidden_layers = HashSet::new();
+
+        f(World {
+            boundaries,
+            hidden_layers: &hidden_layers,
+            circles: &[],
+            doors: &[],
+            portals: &[],
+            index: &index,
+        })
+    }
+
+    fn player_at(pos: Point2, look_dir: Vec2) -> Player {
+        Player {
+            pos,
+            look_dir,
+            target_look_dir: look_dir,
+            ..Player::new()
+        }
+    }
+
+    #[test]
+    fn from_rect_walls_run_left_bottom_right_top() {
+        let walls = Boundary::from_rect(Rect::from_x_y_w_h(0.0, 0.0, 200.0, 100.0));
+        let ends: Vec<(Point2, Point2)> = walls
+            .iter()
+            .map(|wall| (wall.origin, wall.origin + wall.length * wall.dir))
+            .collect();
+
+        let expected = [
+            (pt2(-100.0, -50.0), pt2(-100.0, 50.0)),
+            (pt2(-100.0, -50.0), pt2(100.0, -50.0)),
+            (pt2(100.0, 50.0), pt2(100.0, -50.0)),
+            (pt2(100.0, 50.0), pt2(-100.0, 50.0)),
+        ];
+
+        assert_eq!(ends.len(), expected.len());
+        for ((start, end), (expected_start, expected_end)) in ends.into_iter().zip(expected) {
+            assert_points_close(start, expected_start);
+            assert_points_close(end, expected_end);
+        }
+    }
+
+    #[test]
+    fn from_rect_walls_have_unit_directions_and_side_lengths() {
+        let walls = Boundary::from_rect(Rect::from_x_y_w_h(0.0, 0.0, 200.0, 100.0));
+
+        for (wall, (dir, length)) in walls.iter().zip([
+            (vec2(0.0, 1.0), 100.0),
+            (vec2(1.0, 0.0), 200.0),
+            (vec2(0.0, -1.0), 100.0),
+            (vec2(-1.0, 0.0), 200.0),
+        ]) {
+            assert_points_close(wall.dir, dir);
+            assert_close(wall.length, length);
+        }
+    }
+
+    #[test]
+    fn centre_ray_hits_each_from_rect_wall() {
+        let walls = Boundary::from_rect(Rect::from_x_y_w_h(0.0, 0.0, 200.0, 100.0));
+
+        with_world(&walls, |world| {
+            for (look_dir, distance, end) in [
+                (vec2(-1.0, 0.0), 100.0, pt2(-100.0, 0.0)),
+                (vec2(0.0, -1.0), 50.0, pt2(0.0, -50.0)),
+                (vec2(1.0, 0.0), 100.0, pt2(100.0, 0.0)),
+                (vec2(0.0, 1.0), 50.0, pt2(0.0, 50.0)),
+            ] {
+                let player = player_at(pt2(0.0, 0.0), look_dir);
+                let ray = Ray::cast(&player, 0.0, world, 0, MAX_DISTANCE);
+
+                assert_close(ray.length.expect("the centre ray should hit"), distance);
+                assert_points_close(ray.end.unwrap(), end);
+            }
+        });
+    }
+
+    #[test]
+    fn dda_hits_a_flat_wall_at_a_flat_depth() {
+        // Five columns by four rows of 50, so y runs -100..100 and the wall row's
+        // face is at y = 50.
+        let mut cells = vec![vec![0; 5]; 4];
+        cells[0] = vec![3; 5];
+        let grid = Grid {
+            cells,
+            cell_size: 50.0,
+        };
+        let player = player_at(pt2(0.0, -75.0), vec2(0.0, 1.0));
+
+        for d_theta in [-0.3, 0.0, 0.3] {
+            let ray = Ray::new(&player, d_theta);
+            let (point, depth, side, tile) = grid
+                .cast_dda(&ray, player.look_dir, MAX_DISTANCE)
+                .expect("the ray should hit the wall row");
+
+            assert_close(point.y, 50.0);
+            assert_close(depth, 125.0);
+            assert_close(point.distance(player.pos), 125.0 / d_theta.cos());
+            assert!(matches!(side, WallSide::NorthSouth));
+            assert_eq!(tile, 3);
+        }
+    }
+
+    #[test]
+    fn look_at_faces_the_target_with_a_unit_direction() {
+        let mut player = player_at(pt2(10.0, 20.0), vec2(1.0, 0.0));
+
+        player.look_at(pt2(13.0, 24.0));
+
+        assert_points_close(player.look_dir, vec2(0.6, 0.8));
+        assert_points_close(player.target_look_dir, vec2(0.6, 0.8));
+        assert_close(player.look_dir.length(), 1.0);
+    }
+
+    #[test]
+    fn look_at_own_position_keeps_a_unit_direction() {
+        let mut player = player_at(pt2(10.0, 20.0), vec2(0.0, 1.0));
+
+        player.look_at(player.pos);
+        assert_points_close(player.look_dir, vec2(0.0, 1.0));
+
+        // Close enough that the offset is tiny but not zero.
+        player.look_at(player.pos + vec2(1e-6, 0.0));
+        assert!(player.look_dir.is_finite());
+        assert_close(player.look_dir.length(), 1.0);
+    }
+
+    // Random walls inside a box, from a fixed seed so the scene is the same every run.
+    fn scattered_walls(seed: u64, count: usize) -> Vec<Boundary> {
+        let area = Rect::from_w_h(1000.0, 800.0);
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut walls = Boundary::from_rect(area);
+
+        walls.extend((0..count).map(|_| {
+            let start = pt2(
+                rng.gen_range(area.left()..area.right()),
+                rng.gen_range(area.bottom()..area.top()),
+            );
+            let end = start + vec2(rng.gen_range(-150.0..150.0), rng.gen_range(-150.0..150.0));
+            Boundary::new(start, end)
+        }));
+
+        walls
+    }
+
+    #[test]
+    fn indexed_nearest_hit_matches_exhaustive_scan() {
+        let circles = [
+            CircleBoundary::new(pt2(120.0, 40.0), 30.0),
+            CircleBoundary::new(pt2(-200.0, -150.0), 60.0),
+        ];
+        let hidden_layers = HashSet::new();
+
+        for (seed, count) in [(1, 8), (2, 64), (3, 256)] {
+            let walls = scattered_walls(seed, count);
+            let index = BoundaryIndex::build(&walls);
+            let world = World {
+                boundaries: &walls,
+                hidden_layers: &hidden_layers,
+                circles: &circles,
+                doors: &[],
+                portals: &[],
+                index: &index,
+            };
+
+            for origin in [pt2(0.0, 0.0), pt2(-420.0, 310.0), pt2(333.0, -250.0)] {
+                for step in 0..360 {
+                    let dir = Vec2::X.rotate((step as f32).to_radians());
+
+                    let mut indexed = Ray::from_origin(origin, dir);
+                    indexed.find_nearest(world, f32::INFINITY);
+
+                    let mut exhaustive = Ray::from_origin(origin, dir);
+                    let mut nearest = None;
+                    for (i, obstacle) in world.obstacles().enumerate() {
+                        exhaustive.test_obstacle(obstacle, i, &mut nearest);
+                    }
+
+                    assert_eq!(indexed.length, exhaustive.length);
+                    assert_eq!(indexed.end, exhaustive.end);
+                    assert_eq!(indexed.normal, exhaustive.normal);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn near_zero_depth_is_clamped_to_the_near_clip() {
+        let projection = projection_distance(60.0, 800.0);
+        let near_clip = 2.0;
+        let clamped = projected_wall_height(near_clip, projection, near_clip);
+
+        for depth in [0.0, -0.0, 1e-30, f32::MIN_POSITIVE, 1e-3, 1.0] {
+            let height = projected_wall_height(depth, projection, near_clip);
+
+            assert!(height.is_finite(), "depth {} gave {}", depth, height);
+            assert_close(height, clamped);
+        }
+        assert!(projected_wall_height(10.0, projection, near_clip) < clamped);
+    }
+
+    // Feeds `walls` to `test_obstacle` in the given order, each tagged with its
+    // index in `walls`, and returns the distance and index of the winner.
+    fn nearest_of(ray: &Ray, walls: &[Boundary], visit: &[usize]) -> Option<(f32, usize)> {
+        let mut ray = Ray::from_origin(ray.origin, ray.dir);
+        let mut nearest = None;
+
+        for &i in visit {
+            ray.test_obstacle(Obstacle::Segment(&walls[i]), i, &mut nearest);
+        }
+
+        nearest.map(|(order, distance, _)| (distance, order))
+    }
+
+    #[test]
+    fn shared_corner_is_claimed_by_one_wall_whichever_is_tested_first() {
+        let corner = pt2(100.0, 0.0);
+        let walls = [
+            Boundary::new(pt2(100.0, -50.0), corner),
+            Boundary::new(corner, pt2(150.0, 50.0)),
+        ];
+
+        for d_theta in [-1e-6, 0.0, 1e-6] {
+            let ray = Ray::from_origin(pt2(0.0, 0.0), Vec2::X.rotate(d_theta));
+            let forwards = nearest_of(&ray, &walls, &[0, 1]).expect("no seam at the corner");
+            let backwards = nearest_of(&ray, &walls, &[1, 0]).expect("no seam at the corner");
+
+            assert_close(forwards.0, 100.0);
+            assert_eq!(forwards.1, 0);
+            assert_eq!(backwards.1, 0);
+        }
+    }
+
+    #[test]
+    fn chained_near_ties_stay_within_epsilon_of_the_nearest_hit() {
+        // Each wall is just inside the tie window of the one before it, and lower
+        // in order, so comparing against the current winner would creep outwards.
+        let step = CORNER_EPSILON * 0.9;
+        let walls: Vec<Boundary> = (0..6)
+            .map(|i| {
+                let x = 100.0 + step * (5 - i) as f32;
+                Boundary::new(pt2(x, -10.0), pt2(x, 10.0))
+            })
+            .collect();
+        let ray = Ray::from_origin(pt2(0.0, 0.0), Vec2::X);
+
+        let (distance, order) = nearest_of(&ray, &walls, &[5, 4, 3, 2, 1, 0]).unwrap();
+
+        assert!(
+            distance <= 100.0 + CORNER_EPSILON,
+            "crept out to {}",
+            distance
+        );
+        assert_eq!(order, 4);
+    }
+
+    #[test]
+    fn cached_column_angles_match_the_inline_formula() {
+        for (fov, resolution, width) in [(60.0, 5, 800.0), (90.0, 1, 1024.0), (45.0, 20, 640.0)] {
+            let angles = ColumnAngles::new(fov, resolution, width);
+            let half_width = width / 2.0;
+
+            for &(column, cached) in &angles.columns {
+                let screen_x = (column as f32 + 0.5) / (half_width / resolution as f32);
+                let inline = (screen_x * (fov.to_radians() / 2.0).tan()).atan();
+
+                assert_close(cached, inline);
+            }
+        }
+    }
+
+    #[test]
+    fn column_angles_rebuild_only_when_an_input_changes() {
+        let mut angles = ColumnAngles::new(60.0, 5, 800.0);
+        let before = angles.columns.as_ptr();
+
+        angles.update(60.0, 5, 800.0, 0.0);
+        assert_eq!(angles.columns.as_ptr(), before);
+
+        angles.update(90.0, 5, 800.0, 0.0);
+        assert_close(
+            angles.columns[0].1,
+            ColumnAngles::new(90.0, 5, 800.0).columns[0].1,
+        );
+    }
+
+    #[test]
+    fn ray_parallel_to_a_wall_misses_without_nan() {
+        let walls = [
+            // Alongside the ray, and lying right on its path.
+            Boundary::new(pt2(0.0, 10.0), pt2(100.0, 10.0)),
+            Boundary::new(pt2(50.0, 0.0), pt2(100.0, 0.0)),
+        ];
+        let ray = Ray::from_origin(pt2(0.0, 0.0), Vec2::X);
+
+        for wall in &walls {
+            assert!(ray.intersect(wall).is_none());
+        }
+
+        with_world(&walls, |world| {
+            let player = player_at(pt2(0.0, 0.0), Vec2::X);
+            let ray = Ray::cast(&player, 0.0, world, 0, MAX_DISTANCE);
+
+            assert!(ray.length.is_none());
+            assert!(ray.end.is_none());
+            assert!(ray.dir.is_finite());
+        });
+    }
+
+    #[test]
+    fn antialiasing_covers_edges_the_centre_ray_misses() {
+        let angles = ColumnAngles::with_offset(60.0, 5, 800.0, 0.0);
+        let (column, centre) = angles.columns[angles.columns.len() / 2];
+        let player = player_at(pt2(0.0, 0.0), Vec2::X);
+
+        // Only the left half of the column sees a wall, so the centre ray misses.
+        let columns = cast_columns(&player, &angles, 4, |angle| {
+            let mut ray = Ray::from_origin(pt2(0.0, 0.0), Vec2::X.rotate(angle));
+            if angle < centre - 1e-6 {
+                ray.length = Some(100.0);
+                ray.luminosity = Some(1.0);
+                ray.color = rgb(1.0, 0.0, 0.0);
+            }
+            (ray, Some(100.0))
+        });
+        let result = columns
+            .iter()
+            .find(|result| result.column == column)
+            .unwrap();
+
+        // Two of four samples hit, so the column stands half as tall.
+        assert!(result.length.is_some());
+        assert_close(result.depth, 200.0);
+        assert_close(result.luminosity.unwrap(), 0.5);
+        assert_close(result.ray.color.red, 1.0);
+    }
+}
@@ -1,20 +1,196 @@
+use nannou::image::{self, GenericImageView, RgbaImage};
 use nannou::prelude::*;
+use nannou::rand::rngs::StdRng;
+use nannou::rand::{Rng, SeedableRng};
 use nannou::winit::event::{DeviceEvent, ElementState, KeyboardInput};
+use rust_visual_nannou::*;
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::env;
+use std::fs;
+use std::io;
+use std::process;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const MAP_PATH: &str = "map.txt";
+const BINDINGS_PATH: &str = "bindings.txt";
+const SPRITES_PATH: &str = "sprites.txt";
+const VIEWMODEL_PATH: &str = "viewmodel.png";
+const SCENE_PATH: &str = "scene.json";
+const RECORDING_PATH: &str = "recording.jsonl";
+const EXPORT_PATH: &str = "map_export.txt";
+/// Where unsaved edits go on quit, inside the system temp directory.
+const AUTOSAVE_FILE: &str = "rust-raycaster-autosave.json";
+const SCREENSHOT_DIR: &str = "screenshots";
+#[cfg(target_os = "linux")]
+const GAMEPAD_PATH: &str = "/dev/input/js0";
+const MAP_POLL_SECONDS: f32 = 0.5;
+const USAGE: &str = "usage: rust-visual-nannou [--fov DEGREES] [--resolution PIXELS] [--map PATH] [--fullscreen] [--compass CORNER] [--aa SAMPLES] [--rays COUNT] [--stride LENGTH] [--headless] [MAP]";
+const FLOOR_BANDS: i32 = 32;
+const FRAME_SAMPLES: usize = 60;
+/// Columns and rows of the `--headless` frame. A terminal cell is about twice as
+/// tall as it is wide, so each row stands for two pixels.
+const ASCII_SIZE: (usize, usize) = (80, 30);
+const MIN_FOV: f32 = 30.0;
+const MAX_FOV: f32 = 120.0;
+const RENDER_SCALES: [f32; 4] = [1.0, 0.75, 0.5, 0.25];
+const MIN_RESOLUTION: i32 = 1;
+const MAX_RESOLUTION: i32 = 20;
+const MIN_RAY_COUNT: i32 = 2;
+const MAX_RAY_COUNT: i32 = 4096;
+const FOV_SPEED: f32 = 60.0;
+const SPAWN_SLOTS: usize = 9;
+const MINIMAP_SIZE: (f32, f32) = (200.0, 150.0);
+const DEBUG_ZOOM_STEP: f32 = 1.1;
+const DEBUG_ZOOM_RANGE: (f32, f32) = (0.05, 20.0);
+// Touchpads scroll in pixels; this many count as one wheel notch.
+const PIXELS_PER_SCROLL_LINE: f32 = 40.0;
+const EXPLORE_CELL_SIZE: f32 = 25.0;
+/// Rays cast across the view each frame to find what the player can see.
+const EXPLORE_RAYS: usize = 48;
+const PITCH_SPEED: f32 = 1.5;
+const USE_DISTANCE: f32 = 40.0;
+const STICK_DEADZONE: f32 = 0.15;
+/// Pieces each wireframe edge is split into when its height follows the ray length
+/// and so curves across the screen.
+const WIREFRAME_SEGMENTS: usize = 16;
+const MAZE_SIZE: (usize, usize) = (12, 9);
+const MAZE_CELL_SIZE: f32 = 60.0;
+/// Fraction of the view height the held item takes up.
+const VIEWMODEL_SCALE: f32 = 0.4;
+/// How far the held item swings side to side while walking, in pixels.
+const VIEWMODEL_SWAY: f32 = 12.0;
+
+// `model` is a plain function pointer, so the parsed options reach it through here.
+static OPTIONS: OnceLock<Options> = OnceLock::new();
 
 struct Model {
     player: Player,
     moves: Moves,
+    movement: MovementConfig,
+    bindings: KeyBindings,
+    key_edges: KeyEdges,
     boundaries: Vec<Boundary>,
+    circles: Vec<CircleBoundary>,
+    doors: Vec<Door>,
+    portals: Vec<Portal>,
+    lights: Vec<Light>,
+    sprites: Vec<Sprite>,
+    /// The held item, drawn over the bottom of the first-person view. It's uploaded
+    /// once and drawn as a single textured quad.
+    viewmodel: Option<wgpu::Texture>,
+    index: BoundaryIndex,
+    /// Everything the player can reach; a backstop against collision drift.
+    bounds: Rect,
+    grid: Grid,
+    use_grid: bool,
     resolution: i32,
+    /// Rays cast across the view, interpolated out to the columns; 0 casts one
+    /// ray per column instead.
+    ray_count: i32,
+    /// The FOV being drawn, easing towards `target_fov`, or `zoom_fov` while zoomed.
     fov: f32,
-    show2D: bool,
-}
-
-struct Player {
-    pos: Point2,
-    look_dir: Vec2,
+    /// The FOV set with `[` and `]`, which the view returns to after zooming.
+    target_fov: f32,
+    zoom_fov: f32,
+    /// How quickly the FOV eases, as a rate per second.
+    zoom_speed: f32,
+    column_angles: ColumnAngles,
+    /// What each column saw when the scene was last cast, reused until `dirty`.
+    columns: Vec<ColumnResult>,
+    /// Set whenever input or animation may have changed what the cameras see, so
+    /// the columns are cast again; idle frames draw the old ones.
+    dirty: bool,
+    /// The other players in split screen. The window is sliced evenly between
+    /// them, with `player` in the leftmost slice and these in order to its right.
+    split_players: Vec<SplitPlayer>,
+    max_bounces: u32,
+    /// Nearest depth a wall is drawn at; anything closer is drawn as if this far.
+    near_clip: f32,
+    /// How far rays look for walls; anything further counts as a miss.
+    max_distance: f32,
+    /// Size columns by the distance to the camera plane rather than along the ray.
+    /// Turning it off shows the fisheye bowing the correction removes.
+    fisheye_correction: bool,
+    /// Keep the columns at fixed world angles while turning, to cut shimmer.
+    stable_columns: bool,
+    /// Fraction of the window size the 3D view is cast and drawn at before being
+    /// scaled up to fill it; one of `RENDER_SCALES`.
+    render_scale: f32,
+    /// Rays averaged per column to soften wall edges; 1 turns antialiasing off.
+    aa_samples: u32,
+    view_mode: ViewMode,
+    sky: SkyGradient,
+    floor_color: Rgb,
+    fog: FogConfig,
+    mouse_sensitivity: f32,
+    /// Exponent applied to the shading curve; above 1 darkens the distance faster.
+    shading_gamma: f32,
+    /// Light everything gets regardless of distance, on top of the player's lamp.
+    ambient: f32,
+    /// How much of the brightness depends on facing the viewer, from 0 (none) to 1
+    /// (walls seen edge-on go black).
+    normal_shading: f32,
+    /// Brightness multiplier on walls facing north or south, the classic cheap
+    /// depth cue; 1 leaves them as lit.
+    side_shading: f32,
+    frame_times: VecDeque<f32>,
+    show_fps: bool,
+    show_debug: bool,
+    minimap: MiniMap,
+    show_minimap: bool,
+    /// What the player has seen so far, hidden on the minimap and in the top-down
+    /// view until then. Starts over when a scene is loaded.
+    explored: ExploredMap,
+    crosshair: Crosshair,
+    show_crosshair: bool,
+    outlines: Outlines,
+    show_outlines: bool,
+    /// Outline every wall's projected edges over the first-person view, hidden or
+    /// not, to check the projection against the columns.
+    show_wireframe: bool,
+    line_weights: LineWeights,
+    /// Pan and zoom of the 2D debug view: a world point `p` is drawn at
+    /// `p * cam_zoom + cam_offset`.
+    cam_offset: Vec2,
+    cam_zoom: f32,
+    /// Where the cursor was last seen while the middle button is held to pan.
+    pan_from: Option<Point2>,
+    compass: Compass,
+    show_compass: bool,
+    noclip: bool,
+    paused: bool,
+    /// Whether the cursor should be grabbed and hidden for mouse-look; it is still
+    /// released in 2D mode and while paused.
+    cursor_grab: bool,
+    /// Seconds of unpaused simulation, which drives moving boundaries.
+    time: f32,
+    edit_start: Option<Point2>,
+    /// Round placed wall ends to multiples of `snap_size`.
+    snap_to_grid: bool,
+    snap_size: f32,
+    /// Walls have been placed or removed since the scene was last saved or loaded.
+    unsaved_edits: bool,
+    maze_seed: u64,
+    /// Footsteps taken so far, and how loud the last one was; see `on_step`.
+    footsteps: u64,
+    step_loudness: f32,
+    /// Layers toggled off with Ctrl and their number key.
+    hidden_layers: HashSet<u32>,
+    /// Slot `i` is jumped to with key `i + 1`, and Shift stores the player there.
+    spawns: [Option<Spawn>; SPAWN_SLOTS],
+    gamepad: Option<Gamepad>,
+    record: Option<Recorder>,
+    playback: Option<Playback>,
+    /// Set for text maps, which are reloaded whenever the file changes.
+    map_watch: Option<MapWatch>,
+    /// Why the last reload failed, shown until the file loads again.
+    map_error: Option<String>,
 }
 
+#[derive(Clone, Copy, Serialize, Deserialize)]
 struct Moves {
     up: bool,
     down: bool,
@@ -22,21 +198,284 @@ struct Moves {
     right: bool,
     clock: bool,
     anti_clock: bool,
+    narrow_fov: bool,
+    widen_fov: bool,
+    look_up: bool,
+    look_down: bool,
+    /// Held to zoom in to `Model::zoom_fov`.
+    #[serde(default)]
+    zoom: bool,
+    /// Analog strafe (x) and forward (y) intent from a stick, each in -1..1.
+    stick_move: Vec2,
+    /// Analog turn intent from a stick, in -1..1.
+    stick_turn: f32,
+    /// Mouse movement since the last update, already in radians: turn in x and
+    /// pitch in y.
+    mouse_look: Vec2,
+}
+
+/// Walking has momentum through `accel` and `friction`. Turning has none: the view
+/// just eases towards where the input points it, at `turn_follow_speed`.
+struct MovementConfig {
+    move_speed: f32,
+    turn_speed: f32,
+    /// Shapes analog turn input: 1 is linear, 2 quadratic and so on.
+    turn_exponent: f32,
+    /// How fast velocity approaches the input, in units per second squared.
+    accel: f32,
+    /// How fast velocity decays once there's no input, in units per second squared.
+    friction: f32,
+    /// Fastest the view catches up with the target direction, in radians per second.
+    turn_follow_speed: f32,
+    /// Speed multiplier while flying through walls.
+    noclip_speed: f32,
+    /// Distance walked per footstep.
+    stride: f32,
+}
+
+/// Keys currently held, so a toggle fires once per press rather than once per
+/// key-repeat event.
+#[derive(Default)]
+struct KeyEdges {
+    held: HashSet<Key>,
+}
+
+/// A split-screen player beyond the first, with their own keys and columns.
+struct SplitPlayer {
+    player: Player,
+    moves: Moves,
+    bindings: KeyBindings,
+    /// Like `Model::column_angles` and `Model::columns`, for this player's slice.
+    angles: ColumnAngles,
+    columns: Vec<ColumnResult>,
+}
+
+struct KeyBindings {
+    forward: Key,
+    back: Key,
+    strafe_left: Key,
+    strafe_right: Key,
+    turn_left: Key,
+    turn_right: Key,
+    /// Steps through the `ViewMode`s.
+    cycle_view: Key,
+}
+
+/// The first line of a recording: where the run starts, and the state of the level
+/// that input can change.
+#[derive(Serialize, Deserialize)]
+struct RecordingStart {
+    player: Player,
+    time: f32,
+    #[serde(default)]
+    noclip: bool,
+    #[serde(default)]
+    hidden_layers: HashSet<u32>,
+    /// How far open each door is and which way it's going, in map order.
+    #[serde(default)]
+    doors: Vec<(f32, DoorState)>,
+}
+
+/// One simulated frame of input, with the frame time it was simulated over.
+#[derive(Serialize, Deserialize)]
+struct RecordedFrame {
+    dt: f32,
+    moves: Moves,
+    /// Taken before the frame's movement, as they were live.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    actions: Vec<RecordedAction>,
+}
+
+/// One-off input that changes the level or the player outside of `Moves`.
+#[derive(Clone, Copy, Serialize, Deserialize)]
+enum RecordedAction {
+    UseDoor,
+    ToggleNoclip,
+    ToggleLayer(u32),
+    UseSpawn(Spawn),
+    LookAt(Point2),
+}
+
+struct Recorder {
+    start: RecordingStart,
+    frames: Vec<RecordedFrame>,
+    /// Actions taken since the last frame, saved with the next one.
+    pending: Vec<RecordedAction>,
+}
+
+struct Playback {
+    frames: VecDeque<RecordedFrame>,
+}
+
+/// Polls a map file's modification time so edits made outside the app are picked
+/// up while it runs.
+struct MapWatch {
+    path: String,
+    modified: Option<SystemTime>,
+    /// Seconds since the file was last checked.
+    since_check: f32,
+}
+
+/// Everything a text map describes, parsed from one read of the file.
+struct MapFile {
+    boundaries: Vec<Boundary>,
+    circles: Vec<CircleBoundary>,
+    doors: Vec<Door>,
+    portals: Vec<Portal>,
+    lights: Vec<Light>,
+    spawns: [Option<Spawn>; SPAWN_SLOTS],
+}
+
+#[derive(Serialize, Deserialize)]
+struct Scene {
+    player: Player,
+    fov: f32,
+    resolution: i32,
+    boundaries: Vec<Boundary>,
+    circles: Vec<CircleBoundary>,
+    #[serde(default)]
+    doors: Vec<Door>,
+    #[serde(default)]
+    portals: Vec<Portal>,
+    #[serde(default)]
+    lights: Vec<Light>,
+    #[serde(default)]
+    spawns: [Option<Spawn>; SPAWN_SLOTS],
+}
+
+struct FogConfig {
+    color: Rgb,
+    start: f32,
+    end: f32,
+}
+
+struct Crosshair {
+    color: Rgb,
+    /// Length of each arm from the centre, in pixels.
+    size: f32,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum ViewMode {
+    FirstPerson,
+    /// The level from above with every cast ray drawn, where walls can be edited.
+    RayDebug,
+    /// The level from above, turned so the player always faces up.
+    TopDown,
+}
+
+impl ViewMode {
+    fn next(self) -> ViewMode {
+        match self {
+            ViewMode::FirstPerson => ViewMode::RayDebug,
+            ViewMode::RayDebug => ViewMode::TopDown,
+            ViewMode::TopDown => ViewMode::FirstPerson,
+        }
+    }
+}
+
+/// The ceiling colour, blended from the horizon up to the top of the window.
+struct SkyGradient {
+    horizon: Rgb,
+    zenith: Rgb,
+}
+
+#[derive(Clone, Copy)]
+enum Corner {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+}
+
+impl Corner {
+    fn from_name(name: &str) -> Option<Corner> {
+        match name {
+            "top-left" => Some(Corner::TopLeft),
+            "top-right" => Some(Corner::TopRight),
+            "bottom-left" => Some(Corner::BottomLeft),
+            "bottom-right" => Some(Corner::BottomRight),
+            _ => None,
+        }
+    }
+}
+
+/// Heading readout, e.g. "N 12°", with north along +y and degrees increasing clockwise.
+struct Compass {
+    corner: Corner,
+    color: Rgb,
+}
+
+/// Line widths and marker sizes for the top-down views, in pixels.
+struct LineWeights {
+    boundary: f32,
+    ray: f32,
+    /// Diameter of the dot marking the player.
+    player: f32,
+}
+
+/// Dark lines where the depth jumps between neighbouring columns, for an inked look.
+struct Outlines {
+    /// Smallest depth difference, in world units, that counts as an edge.
+    threshold: f32,
+    color: Rgb,
+}
+
+struct MiniMap {
+    rect: Rect,
+    scale: f32,
+}
+
+/// Which parts of the level the player has seen, on a coarse grid over its bounds.
+struct ExploredMap {
+    bounds: Rect,
+    cols: usize,
+    rows: usize,
+    seen: Vec<bool>,
+}
+
+/// A point light. The player carries its own headlamp through the ray luminosity,
+/// so these are extra lights placed in the scene.
+#[derive(Clone, Serialize, Deserialize)]
+struct Light {
+    pos: Point2,
+    intensity: f32,
+}
+
+/// A place and heading the number keys jump the player to, for getting back to
+/// exactly the same view.
+#[derive(Clone, Copy, Serialize, Deserialize)]
+struct Spawn {
+    pos: Point2,
+    look_dir: Vec2,
 }
 
-#[derive(Clone)]
-struct Boundary {
-    origin: Point2,
-    dir: Vec2,
-    length: f32,
+struct Sprite {
+    pos: Point2,
+    /// Which way the sprite faces; only matters with directional textures.
+    facing: Vec2,
+    /// Either one texture for all angles, or eight: slot 0 seen from the front and
+    /// the rest counter-clockwise around the sprite in 45 degree steps.
+    textures: Vec<RgbaImage>,
 }
 
-struct Ray {
-    origin: Point2,
-    dir: Vec2,
-    end: Option<Point2>,
-    length: Option<f32>,
-    luminosity: Option<f32>,
+impl KeyEdges {
+    fn any_held(&self, keys: &[Key]) -> bool {
+        keys.iter().any(|key| self.held.contains(key))
+    }
+
+    /// The key, if this event is its transition from released to pressed.
+    fn just_pressed(&mut self, input: KeyboardInput) -> Option<Key> {
+        let key = input.virtual_keycode?;
+
+        match input.state {
+            ElementState::Pressed => self.held.insert(key).then_some(key),
+            ElementState::Released => {
+                self.held.remove(&key);
+                None
+            }
+        }
+    }
 }
 
 impl Moves {
@@ -48,10 +487,54 @@ impl Moves {
             right: false,
             clock: false,
             anti_clock: false,
+            narrow_fov: false,
+            widen_fov: false,
+            look_up: false,
+            look_down: false,
+            zoom: false,
+            stick_move: vec2(0.0, 0.0),
+            stick_turn: 0.0,
+            mouse_look: vec2(0.0, 0.0),
+        }
+    }
+
+    fn update_gamepad(&mut self, axes: GamepadAxes) {
+        // The joystick API reports stick-up as negative y.
+        self.stick_move = apply_deadzone(vec2(axes.left_x, -axes.left_y));
+        self.stick_turn = apply_deadzone(vec2(axes.right_x, 0.0)).x;
+    }
+
+    fn update_moves(&mut self, key: KeyboardInput, bindings: &KeyBindings) {
+        if let Some(data) = key.virtual_keycode {
+            let state = match key.state {
+                ElementState::Pressed => true,
+                ElementState::Released => false,
+            };
+
+            if data == bindings.forward {
+                self.up = state;
+            }
+            if data == bindings.strafe_left {
+                self.left = state;
+            }
+            if data == bindings.back {
+                self.down = state;
+            }
+            if data == bindings.strafe_right {
+                self.right = state;
+            }
+            if data == bindings.turn_right {
+                self.clock = state;
+            }
+            if data == bindings.turn_left {
+                self.anti_clock = state;
+            }
         }
     }
 
-    fn update_moves(&mut self, key: KeyboardInput) {
+    // Pitch, FOV and zoom keys aren't part of the bindings; they only steer the
+    // first player, so split-screen players don't all look up at once.
+    fn update_view_moves(&mut self, key: KeyboardInput) {
         if let Some(data) = key.virtual_keycode {
             let state = match key.state {
                 ElementState::Pressed => true,
@@ -59,284 +542,3650 @@ impl Moves {
             };
 
             match data {
-                Key::W => self.up = state,
-                Key::A => self.left = state,
-                Key::S => self.down = state,
-                Key::D => self.right = state,
-                Key::Right => self.clock = state,
-                Key::Left => self.anti_clock = state,
+                Key::LBracket => self.narrow_fov = state,
+                Key::RBracket => self.widen_fov = state,
+                Key::PageUp => self.look_up = state,
+                Key::PageDown => self.look_down = state,
+                Key::Z => self.zoom = state,
                 _ => {}
             }
         }
     }
 
-    fn update_player(&self, player: &mut Player) {
-        let mut update_vec = vec2(0.0, 0.0);
-        let mut update_theta = 0.0;
+    fn update_fov(&self, fov: f32, dt: f32) -> f32 {
+        let mut update_fov = 0.0;
+
+        if self.narrow_fov {
+            update_fov -= FOV_SPEED * dt;
+        }
+        if self.widen_fov {
+            update_fov += FOV_SPEED * dt;
+        }
+
+        (fov + update_fov).clamp(MIN_FOV, MAX_FOV)
+    }
+
+    fn update_player(
+        &self,
+        player: &mut Player,
+        world: World,
+        config: &MovementConfig,
+        noclip: bool,
+        dt: f32,
+    ) -> u32 {
+        let mut wish = vec2(0.0, 0.0);
+        let mut turn = 0.0;
+
+        let turn_step = config.turn_speed * dt;
 
         if self.up {
-            update_vec += player.look_dir * 2.5;
+            wish += player.look_dir;
         }
         if self.down {
-            update_vec -= player.look_dir * 2.5;
+            wish -= player.look_dir;
         }
         if self.left {
-            update_vec -= player.look_dir.perp() * 2.5;
+            wish -= player.look_dir.perp();
         }
         if self.right {
-            update_vec += player.look_dir.perp() * 2.5;
+            wish += player.look_dir.perp();
         }
         if self.clock {
-            update_theta += 0.05;
+            turn += 1.0;
         }
         if self.anti_clock {
-            update_theta -= 0.05;
+            turn -= 1.0;
+        }
+
+        wish += player.look_dir * self.stick_move.y;
+        wish += player.look_dir.perp() * self.stick_move.x;
+        // Past the exponent, small turns stay slow for aiming while full input still
+        // gives the full turn speed. Keys are always full input.
+        let turn: f32 = (turn + self.stick_turn).clamp(-1.0, 1.0);
+        let update_theta = turn.signum() * turn.abs().powf(config.turn_exponent) * turn_step;
+
+        // Speed up towards the input at `accel`, or coast to a stop at `friction`.
+        let target = wish * config.move_speed;
+        let rate = if wish == Vec2::ZERO {
+            config.friction
+        } else {
+            config.accel
+        };
+        let change = target - player.velocity;
+        let max_change = rate * dt;
+        player.velocity += if change.length() > max_change {
+            change.normalize() * max_change
+        } else {
+            change
+        };
+
+        let update_vec = player.velocity * dt;
+
+        let moved = if noclip {
+            let moved = update_vec * config.noclip_speed;
+            player.pos += moved;
+            moved
+        } else {
+            let moved = player.update_player_pos(update_vec, world);
+
+            // Momentum into a wall is lost rather than stored up.
+            if dt > 0.0 {
+                player.velocity = moved / dt;
+            }
+            moved
+        };
+        player.update_bob(moved.length(), dt);
+        player.turn(update_theta);
+        player.turn_towards_target(config.turn_follow_speed * dt);
+
+        if self.look_up {
+            player.update_player_pitch(PITCH_SPEED * dt);
         }
+        if self.look_down {
+            player.update_player_pitch(-PITCH_SPEED * dt);
+        }
+
+        player.update_stride(moved.length(), config.stride)
+    }
+}
 
-        player.update_player_pos(update_vec);
-        player.update_player_look_dir(update_theta);
+impl MovementConfig {
+    // Per-second rates matching the old fixed per-frame steps at 60 FPS.
+    fn new() -> MovementConfig {
+        MovementConfig {
+            move_speed: 150.0,
+            turn_speed: 3.0,
+            turn_exponent: 2.0,
+            accel: 1200.0,
+            friction: 900.0,
+            turn_follow_speed: 12.0,
+            noclip_speed: 3.0,
+            stride: 40.0,
+        }
     }
 }
 
-impl Player {
-    fn show_player(&self, draw: &Draw) {
-        draw.ellipse().w_h(10.0, 10.0).xy(self.pos);
+#[derive(Clone, Copy, Default)]
+struct GamepadAxes {
+    left_x: f32,
+    left_y: f32,
+    right_x: f32,
+}
 
-        draw.line()
-            .start(self.pos)
-            .end(self.pos + (50.0 * self.look_dir))
-            .weight(2.0)
-            .color(RED);
+/// A joystick read through the Linux joystick API on a background thread, so the
+/// blocking reads never stall a frame. Other platforms have no gamepad support.
+struct Gamepad {
+    axes: Arc<Mutex<GamepadAxes>>,
+}
+
+impl Gamepad {
+    #[cfg(target_os = "linux")]
+    fn open(path: &str) -> io::Result<Gamepad> {
+        let mut file = fs::File::open(path)?;
+        let axes = Arc::new(Mutex::new(GamepadAxes::default()));
+        let shared = Arc::clone(&axes);
+
+        std::thread::spawn(move || {
+            use std::io::Read;
+
+            // Each event is `u32 time, i16 value, u8 type, u8 number`.
+            let mut event = [0u8; 8];
+
+            while file.read_exact(&mut event).is_ok() {
+                const AXIS_EVENT: u8 = 0x02;
+
+                if event[6] & AXIS_EVENT == 0 {
+                    continue;
+                }
+
+                let value = i16::from_le_bytes([event[4], event[5]]) as f32 / i16::MAX as f32;
+                let mut axes = shared.lock().unwrap();
+
+                match event[7] {
+                    0 => axes.left_x = value,
+                    1 => axes.left_y = value,
+                    3 => axes.right_x = value,
+                    _ => {}
+                }
+            }
+
+            // The pad was unplugged or the read failed; let go of the sticks rather
+            // than leaving the player moving on the last values seen.
+            *shared.lock().unwrap() = GamepadAxes::default();
+        });
+
+        Ok(Gamepad { axes })
     }
 
-    fn update_player_pos(&mut self, vel: Vec2) {
-        self.pos += vel;
+    fn axes(&self) -> GamepadAxes {
+        *self.axes.lock().unwrap()
     }
+}
+
+/// Zeroes stick input inside the deadzone and rescales the rest, so motion ramps up
+/// from zero at the deadzone edge instead of jumping.
+fn apply_deadzone(stick: Vec2) -> Vec2 {
+    let magnitude = stick.length();
 
-    fn update_player_look_dir(&mut self, d_theta: f32) {
-        self.look_dir = self.look_dir.rotate(d_theta);
-        self.look_dir = self.look_dir.normalize();
+    if magnitude <= STICK_DEADZONE {
+        return vec2(0.0, 0.0);
     }
 
-    fn new() -> Player {
-        Player {
-            pos: pt2(0.0, 0.0),
-            look_dir: vec2(1.0, 0.0),
+    let scaled = ((magnitude - STICK_DEADZONE) / (1.0 - STICK_DEADZONE)).min(1.0);
+    stick * (scaled / magnitude)
+}
+
+impl KeyBindings {
+    fn new() -> KeyBindings {
+        KeyBindings {
+            forward: Key::W,
+            back: Key::S,
+            strafe_left: Key::A,
+            strafe_right: Key::D,
+            turn_left: Key::Left,
+            turn_right: Key::Right,
+            cycle_view: Key::V,
         }
     }
-}
 
-impl Ray {
-    fn new(player: &Player, d_theta: f32) -> Ray {
-        Ray {
-            origin: player.pos,
-            dir: player.look_dir.rotate(d_theta).normalize(),
-            end: None,
-            length: None,
-            luminosity: None,
+    // The keys for each split-screen player after the first, which is as many as
+    // can join: IJKL to walk and turn, with strafing just below on comma and period.
+    fn split_players() -> Vec<KeyBindings> {
+        vec![KeyBindings {
+            forward: Key::I,
+            back: Key::K,
+            strafe_left: Key::Comma,
+            strafe_right: Key::Period,
+            turn_left: Key::J,
+            turn_right: Key::L,
+            cycle_view: Key::V,
+        }]
+    }
+
+    // One binding per line as `action = key`, e.g. `forward = Up`. Actions that
+    // aren't listed keep their default key.
+    fn from_file(path: &str) -> io::Result<KeyBindings> {
+        let contents = fs::read_to_string(path)?;
+        let mut bindings = KeyBindings::new();
+
+        for (index, line) in config_lines(&contents) {
+            let (action, name) = line.split_once('=').ok_or_else(|| {
+                parse_error(
+                    path,
+                    index,
+                    &format!("expected `action = key`, found `{}`", line),
+                )
+            })?;
+
+            let key = parse_key(name.trim()).ok_or_else(|| {
+                parse_error(path, index, &format!("unknown key `{}`", name.trim()))
+            })?;
+
+            match action.trim() {
+                "forward" => bindings.forward = key,
+                "back" => bindings.back = key,
+                "strafe_left" => bindings.strafe_left = key,
+                "strafe_right" => bindings.strafe_right = key,
+                "turn_left" => bindings.turn_left = key,
+                "turn_right" => bindings.turn_right = key,
+                "cycle_view" => bindings.cycle_view = key,
+                other => {
+                    return Err(parse_error(
+                        path,
+                        index,
+                        &format!("unknown action `{}`", other),
+                    ))
+                }
+            }
         }
+
+        Ok(bindings)
     }
+}
+
+impl Light {
+    // Lights share the map file too, as `light x,y intensity`.
+    fn from_map(path: &str, contents: &str) -> io::Result<Vec<Light>> {
+        let mut return_val: Vec<Light> = Vec::new();
 
-    fn intersect(&self, boundary: &Boundary, player: &Player) -> Option<(Point2, f32)> {
-        let determinant = (self.dir.x * boundary.dir.y) - (boundary.dir.x * self.dir.y);
-        let k = (self.dir.x * (self.origin.y - boundary.origin.y))
-            - (self.dir.y * (self.origin.x - boundary.origin.x));
+        for (index, line) in config_lines(contents) {
+            let fields: Vec<&str> = match line.strip_prefix("light ") {
+                Some(fields) => fields.split_whitespace().collect(),
+                None => continue,
+            };
 
-        let lambda = (boundary.dir.x * (self.origin.y - boundary.origin.y))
-            - (boundary.dir.y * (self.origin.x - boundary.origin.x));
+            if fields.len() != 2 {
+                return Err(parse_error(
+                    path,
+                    index,
+                    &format!("expected `light x,y intensity`, found `{}`", line),
+                ));
+            }
 
-        let k = k / determinant;
-        let lambda = lambda / determinant;
+            let pos = parse_point(fields[0]).map_err(|msg| parse_error(path, index, &msg))?;
+            let intensity = fields[1].parse::<f32>().map_err(|_| {
+                parse_error(path, index, &format!("invalid intensity `{}`", fields[1]))
+            })?;
 
-        if lambda >= 0.0 && k >= 0.0 && k < boundary.length {
-            return Some((
-                boundary.origin + k * boundary.dir,
-                5000.0 / ((lambda / 5.0) * (lambda / 5.0)) + 0.2,
-            ));
+            return_val.push(Light { pos, intensity });
         }
 
-        None
+        Ok(return_val)
+    }
+
+    // Inverse-square falloff, scaled by how directly the surface faces the light.
+    // The normal faces the viewer, so a light behind the wall contributes nothing.
+    fn illuminate(&self, point: Point2, normal: Option<Vec2>) -> f32 {
+        let to_light = self.pos - point;
+        let distance_squared = to_light.length_squared().max(1.0);
+
+        let facing = match normal {
+            Some(normal) => normal.dot(to_light.normalize_or_zero()).max(0.0),
+            None => 1.0,
+        };
+
+        self.intensity * facing / distance_squared
     }
+}
+
+fn scene_light(lights: &[Light], point: Point2, normal: Option<Vec2>) -> f32 {
+    lights
+        .iter()
+        .map(|light| light.illuminate(point, normal))
+        .sum()
+}
 
-    fn show(&self, draw: &Draw) {
-        match self.end {
-            Some(point) => {
-                draw.line()
-                    .start(self.origin)
-                    .end(point)
-                    .weight(1.0)
-                    .color(BLUE);
+impl Spawn {
+    // Spawn points share the map file too, as `spawn x,y heading`, the heading in
+    // degrees anticlockwise from the x axis. They fill the slots in order.
+    fn from_map(path: &str, contents: &str) -> io::Result<[Option<Spawn>; SPAWN_SLOTS]> {
+        let mut return_val = [None; SPAWN_SLOTS];
+        let mut count = 0;
+
+        for (index, line) in config_lines(contents) {
+            let fields: Vec<&str> = match line.strip_prefix("spawn ") {
+                Some(fields) => fields.split_whitespace().collect(),
+                None => continue,
+            };
+
+            if fields.len() != 2 {
+                return Err(parse_error(
+                    path,
+                    index,
+                    &format!("expected `spawn x,y heading`, found `{}`", line),
+                ));
             }
-            None => {
-                draw.line()
-                    .start(self.origin)
-                    .end(self.origin + 1000.0 * self.dir)
-                    .weight(1.0)
-                    .color(BLUE);
+            if count == SPAWN_SLOTS {
+                return Err(parse_error(
+                    path,
+                    index,
+                    &format!("at most {} spawn points are allowed", SPAWN_SLOTS),
+                ));
             }
+
+            let pos = parse_point(fields[0]).map_err(|msg| parse_error(path, index, &msg))?;
+            let heading = fields[1].parse::<f32>().map_err(|_| {
+                parse_error(path, index, &format!("invalid heading `{}`", fields[1]))
+            })?;
+
+            return_val[count] = Some(Spawn {
+                pos,
+                look_dir: Vec2::X.rotate(heading.to_radians()),
+            });
+            count += 1;
         }
+
+        Ok(return_val)
     }
-}
 
-impl Boundary {
-    fn new(start: Point2, end: Point2) -> Boundary {
-        Boundary {
-            origin: start,
-            dir: (end - start).normalize(),
-            length: (end - start).length(),
+    fn of(player: &Player) -> Spawn {
+        Spawn {
+            pos: player.pos,
+            look_dir: player.look_dir,
         }
     }
 
-    fn from_rect(rect: Rect) -> Vec<Boundary> {
-        let mut return_val: Vec<Boundary> = Vec::new();
+    fn apply(&self, player: &mut Player) {
+        player.pos = self.pos;
+        player.look_dir = self.look_dir;
+        player.target_look_dir = self.look_dir;
+    }
+}
 
-        return_val.push(Boundary::new(
-            pt2(rect.x.start, rect.y.start),
-            pt2(rect.x.start, rect.y.end),
-        ));
-        return_val.push(Boundary::new(
-            pt2(rect.x.start, rect.y.start),
-            pt2(rect.x.end, rect.y.start),
-        ));
-        return_val.push(Boundary::new(
-            pt2(rect.x.end, rect.y.end),
-            pt2(rect.x.end, rect.y.start),
-        ));
-        return_val.push(Boundary::new(
-            pt2(rect.x.end, rect.y.end),
-            pt2(rect.x.start, rect.y.end),
-        ));
+impl MapFile {
+    fn load(path: &str) -> io::Result<MapFile> {
+        let contents = fs::read_to_string(path)?;
 
-        return_val
+        Ok(MapFile {
+            boundaries: Boundary::from_map(path, &contents)?,
+            circles: CircleBoundary::from_map(path, &contents)?,
+            doors: Door::from_map(path, &contents)?,
+            portals: Portal::from_map(path, &contents)?,
+            lights: Light::from_map(path, &contents)?,
+            spawns: Spawn::from_map(path, &contents)?,
+        })
     }
 
-    fn show(&self, draw: &Draw) {
-        draw.line()
-            .start(self.origin)
-            .end(self.origin + self.length * self.dir)
-            .weight(4.0)
-            .color(BLACK);
+    // What's played without a map file: just the window's edges.
+    fn window_edges(window: Rect) -> MapFile {
+        MapFile {
+            boundaries: Boundary::window_edges(window),
+            circles: Vec::new(),
+            doors: Vec::new(),
+            portals: Vec::new(),
+            lights: Vec::new(),
+            spawns: [None; SPAWN_SLOTS],
+        }
     }
 }
 
-fn main() {
-    nannou::app(model)
-        .event(event)
-        .update(update)
-        .simple_window(view)
-        .run();
-}
+impl MapWatch {
+    fn new(path: &str) -> MapWatch {
+        MapWatch {
+            path: path.to_string(),
+            modified: MapWatch::modified(path),
+            since_check: 0.0,
+        }
+    }
 
-fn model(app: &App) -> Model {
-    let mut boundaries: Vec<Boundary> = Vec::new();
+    fn modified(path: &str) -> Option<SystemTime> {
+        fs::metadata(path).and_then(|meta| meta.modified()).ok()
+    }
 
-    let new_bounds = Boundary::from_rect(app.window_rect());
+    // True once per change to the file, checking at most every `MAP_POLL_SECONDS`.
+    fn changed(&mut self, dt: f32) -> bool {
+        self.since_check += dt;
+        if self.since_check < MAP_POLL_SECONDS {
+            return false;
+        }
+        self.since_check = 0.0;
 
-    boundaries.extend_from_slice(&new_bounds);
+        let modified = MapWatch::modified(&self.path);
+        if modified == self.modified {
+            return false;
+        }
+        self.modified = modified;
 
-    Model {
-        player: Player::new(),
-        moves: Moves::new(),
-        boundaries,
-        resolution: 5,
-        fov: 60.0,
-        show2D: false,
+        true
     }
 }
 
-fn event(_app: &App, model: &mut Model, event: Event) {
-    if let Event::DeviceEvent(_, data) = event {
-        if let DeviceEvent::Key(key) = data {
-            model.moves.update_moves(key);
+impl Sprite {
+    // One sprite per line as `x,y texture.png`, or `x,y dx,dy front.png ...` with a
+    // facing direction and eight textures for a directional sprite. Blank lines
+    // and `#` comments are ignored.
+    fn from_file(path: &str) -> io::Result<Vec<Sprite>> {
+        let contents = fs::read_to_string(path)?;
+        let mut return_val: Vec<Sprite> = Vec::new();
+
+        for (index, line) in config_lines(&contents) {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+
+            let (pos, facing, texture_paths) = match fields[..] {
+                [pos, facing, ref textures @ ..] if textures.len() == 8 => {
+                    (pos, Some(facing), textures.to_vec())
+                }
+                _ => {
+                    let (pos, texture_path) =
+                        line.split_once(char::is_whitespace).ok_or_else(|| {
+                            parse_error(
+                                path,
+                                index,
+                                &format!("expected `x,y texture`, found `{}`", line),
+                            )
+                        })?;
+                    (pos, None, vec![texture_path.trim()])
+                }
+            };
+
+            let pos = parse_point(pos).map_err(|msg| parse_error(path, index, &msg))?;
+            let facing = match facing {
+                Some(facing) => parse_point(facing)
+                    .map_err(|msg| parse_error(path, index, &msg))?
+                    .normalize_or_zero(),
+                None => vec2(1.0, 0.0),
+            };
+            let textures = texture_paths
+                .iter()
+                .map(|texture_path| {
+                    load_texture(texture_path).map_err(|msg| parse_error(path, index, &msg))
+                })
+                .collect::<io::Result<Vec<RgbaImage>>>()?;
+
+            return_val.push(Sprite {
+                pos,
+                facing,
+                textures,
+            });
         }
+
+        Ok(return_val)
+    }
+
+    // The texture for a viewer at `eye`: the slot whose direction around the
+    // sprite is closest to the one the viewer is looking from.
+    fn texture_from(&self, eye: Point2) -> &RgbaImage {
+        let slots = self.textures.len();
+        let angle = self.facing.angle_between(eye - self.pos).rem_euclid(TAU);
+        let slot = (angle / (TAU / slots as f32)).round() as usize % slots;
+
+        &self.textures[slot]
     }
 }
 
-fn update(_app: &App, model: &mut Model, _update: Update) {
-    model.moves.update_player(&mut model.player);
+impl Crosshair {
+    fn draw(&self, draw: &Draw, window: Rect) {
+        let centre = window.xy();
+
+        draw.line()
+            .start(centre - vec2(self.size, 0.0))
+            .end(centre + vec2(self.size, 0.0))
+            .weight(2.0)
+            .color(self.color);
+        draw.line()
+            .start(centre - vec2(0.0, self.size))
+            .end(centre + vec2(0.0, self.size))
+            .weight(2.0)
+            .color(self.color);
+    }
 }
 
-fn view(app: &App, model: &Model, frame: Frame) {
-    let draw = app.draw();
-    let boundaries = app.window_rect();
+impl SkyGradient {
+    /// The colour `t` of the way from the horizon (0) to the zenith (1).
+    fn at(&self, t: f32) -> Rgb {
+        rgb(
+            self.horizon.red + (self.zenith.red - self.horizon.red) * t,
+            self.horizon.green + (self.zenith.green - self.horizon.green) * t,
+            self.horizon.blue + (self.zenith.blue - self.horizon.blue) * t,
+        )
+    }
+}
 
-    draw.background().color(PLUM);
+impl Compass {
+    fn heading(look_dir: Vec2) -> (&'static str, f32) {
+        let degrees = (90.0 - look_dir.angle().to_degrees()).rem_euclid(360.0);
+        let cardinal = ["N", "E", "S", "W"][((degrees + 45.0) / 90.0) as usize % 4];
 
-    for i in
-        (boundaries.x.start as i32 / model.resolution)..(boundaries.x.end as i32 / model.resolution)
-    {
-        let angle = (i as f32) / (boundaries.x.end / (model.resolution as f32));
-        let angle = map_range(
-            angle,
-            -1.0,
-            1.0,
-            -((model.fov * 3.14159265) / (2.0 * 180.0)),
-            (model.fov * 3.14159265) / (2.0 * 180.0),
-        );
+        (cardinal, degrees)
+    }
 
-        let mut ray = Ray::new(&model.player, angle);
+    fn draw(&self, draw: &Draw, window: Rect, look_dir: Vec2) {
+        let (cardinal, degrees) = Compass::heading(look_dir);
+        let inner = window
+            .pad_left(50.0)
+            .pad_right(50.0)
+            .pad_top(20.0)
+            .pad_bottom(20.0);
+        let position = match self.corner {
+            Corner::TopLeft => inner.top_left(),
+            Corner::TopRight => inner.top_right(),
+            Corner::BottomLeft => inner.bottom_left(),
+            Corner::BottomRight => inner.bottom_right(),
+        };
 
-        for boundary in &model.boundaries {
-            let new_point = ray.intersect(&boundary, &model.player);
+        draw.text(&format!("{} {:.0}°", cardinal, degrees))
+            .xy(position)
+            .color(self.color);
+    }
+}
 
-            if let Some((point, luminosity)) = new_point {
-                match ray.end {
-                    Some(end) => {
-                        if (point - ray.origin).length() < (end - ray.origin).length() {
-                            ray.end = Some(point);
-                            ray.length = Some((point - ray.origin).length());
-                            ray.luminosity = Some(luminosity);
-                        }
-                    }
-                    None => {
-                        ray.end = Some(point);
-                        ray.length = Some((point - ray.origin).length());
-                        ray.luminosity = Some(luminosity);
-                    }
-                }
-            }
-        }
+impl FogConfig {
+    fn apply(&self, color: Rgb, distance: f32) -> Rgb {
+        let t = ((distance - self.start) / (self.end - self.start)).clamp(0.0, 1.0);
 
-        if model.show2D {
-            ray.show(&draw);
-        } else {
-            let x = i * model.resolution;
+        rgb(
+            color.red + (self.color.red - color.red) * t,
+            color.green + (self.color.green - color.green) * t,
+            color.blue + (self.color.blue - color.blue) * t,
+        )
+    }
+}
 
-            let height = match ray.length {
-                Some(length) => 100000.0 / (length * angle.cos()),
-                None => 0.0,
-            };
+impl MiniMap {
+    // Anchors the map in the bottom right of `window`, scaled so every boundary fits.
+    fn new(window: Rect, boundaries: &[Boundary]) -> MiniMap {
+        let mut minimap = MiniMap {
+            rect: Rect::from_w_h(MINIMAP_SIZE.0, MINIMAP_SIZE.1).bottom_right_of(window.pad(10.0)),
+            scale: 1.0,
+        };
+        minimap.fit(boundaries);
+        minimap
+    }
 
-            let light = match ray.luminosity {
-                Some(lum) => {
-                    if lum > 0.9 {
-                        0.9
-                    } else {
-                        lum
-                    }
-                }
-                None => 0.0,
-            };
+    fn fit(&mut self, boundaries: &[Boundary]) {
+        let extent = boundaries
+            .iter()
+            .flat_map(|boundary| {
+                [
+                    boundary.origin,
+                    boundary.origin + boundary.length * boundary.dir,
+                ]
+            })
+            .fold(vec2(1.0, 1.0), |extent, point| extent.max(point.abs()));
 
-            draw.rect()
-                .x(x as f32)
-                .w_h(model.resolution as f32, height)
-                .color(rgba(
-                    light,
-                    light,
-                    light,
-                    map_range(light, 0.9, 0.2, 1.0, 0.0),
-                ));
-        }
+        self.scale = (self.rect.w() / (2.0 * extent.x)).min(self.rect.h() / (2.0 * extent.y));
     }
 
-    if model.show2D {
-        for boundary in &model.boundaries {
-            boundary.show(&draw);
+    // The same map moved to the bottom right of `area`, for a split-screen view.
+    fn anchored(&self, area: Rect) -> MiniMap {
+        MiniMap {
+            rect: Rect::from_wh(self.rect.wh()).bottom_right_of(area.pad(10.0)),
+            scale: self.scale,
         }
+    }
 
-        model.player.show_player(&draw);
+    fn to_map(&self, point: Point2) -> Point2 {
+        self.rect.xy() + point * self.scale
     }
 
-    draw.to_frame(app, &frame).unwrap();
+    // Walls on hidden layers are left off, as they are everywhere else.
+    fn draw(&self, draw: &Draw, world: World, explored: &ExploredMap, player: &Player) {
+        draw.rect()
+            .xy(self.rect.xy())
+            .wh(self.rect.wh())
+            .color(rgba(0.0, 0.0, 0.0, 0.6));
+
+        let draw = draw.scissor(self.rect);
+
+        for boundary in world
+            .boundaries
+            .iter()
+            .filter(|boundary| world.shows(boundary))
+        {
+            draw.line()
+                .start(self.to_map(boundary.origin))
+                .end(self.to_map(boundary.origin + boundary.length * boundary.dir))
+                .weight(1.5)
+                .color(boundary.color);
+        }
+
+        for circle in world.circles {
+            draw.ellipse()
+                .xy(self.to_map(circle.center))
+                .radius(circle.radius * self.scale)
+                .no_fill()
+                .stroke_weight(1.5)
+                .stroke(circle.color);
+        }
+
+        let mut fog = ColumnMesh::default();
+        for cell in explored.hidden_cells() {
+            let corner = self.to_map(pt2(cell.left(), cell.bottom()));
+            fog.push_rect(
+                Rect::from_corners(corner, corner + cell.wh() * self.scale),
+                rgba(0.0, 0.0, 0.0, 1.0),
+            );
+        }
+        fog.draw(&draw);
+
+        let pos = self.to_map(player.pos);
+
+        draw.ellipse().xy(pos).w_h(5.0, 5.0).color(WHITE);
+        draw.line()
+            .start(pos)
+            .end(pos + 12.0 * player.look_dir)
+            .weight(1.5)
+            .color(RED);
+    }
+}
+
+impl ExploredMap {
+    fn new(bounds: Rect) -> ExploredMap {
+        let cols = (bounds.w() / EXPLORE_CELL_SIZE).ceil().max(1.0) as usize;
+        let rows = (bounds.h() / EXPLORE_CELL_SIZE).ceil().max(1.0) as usize;
+
+        ExploredMap {
+            bounds,
+            cols,
+            rows,
+            seen: vec![false; cols * rows],
+        }
+    }
+
+    fn cell(&self, point: Point2) -> Option<usize> {
+        let col = ((point.x - self.bounds.left()) / EXPLORE_CELL_SIZE).floor();
+        let row = ((point.y - self.bounds.bottom()) / EXPLORE_CELL_SIZE).floor();
+
+        if col < 0.0 || row < 0.0 || col >= self.cols as f32 || row >= self.rows as f32 {
+            return None;
+        }
+
+        Some(row as usize * self.cols + col as usize)
+    }
+
+    // Marks every cell the segment passes through, sampling at half a cell so none
+    // are stepped over.
+    fn reveal_along(&mut self, start: Point2, end: Point2) {
+        let steps = ((end - start).length() / (EXPLORE_CELL_SIZE / 2.0)).ceil() as usize;
+
+        for step in 0..=steps {
+            let point = start.lerp(end, step as f32 / steps.max(1) as f32);
+
+            if let Some(cell) = self.cell(point) {
+                self.seen[cell] = true;
+            }
+        }
+    }
+
+    fn hidden_cells(&self) -> impl Iterator<Item = Rect> + '_ {
+        self.seen
+            .iter()
+            .enumerate()
+            .filter(|(_, &seen)| !seen)
+            .map(move |(i, _)| {
+                let corner = pt2(
+                    self.bounds.left() + (i % self.cols) as f32 * EXPLORE_CELL_SIZE,
+                    self.bounds.bottom() + (i / self.cols) as f32 * EXPLORE_CELL_SIZE,
+                );
+                Rect::from_corners(corner, corner + vec2(EXPLORE_CELL_SIZE, EXPLORE_CELL_SIZE))
+            })
+    }
+}
+
+fn level_bounds(boundaries: &[Boundary], grid: &Grid) -> Rect {
+    let top_left = grid.top_left();
+    let grid_size = vec2(
+        grid.cols() as f32 * grid.cell_size,
+        -(grid.rows() as f32 * grid.cell_size),
+    );
+
+    let points = boundaries
+        .iter()
+        .flat_map(|boundary| {
+            [
+                boundary.origin,
+                boundary.origin + boundary.length * boundary.dir,
+            ]
+        })
+        .chain([top_left, top_left + grid_size]);
+
+    let (min, max) = points.fold(
+        (pt2(f32::MAX, f32::MAX), pt2(f32::MIN, f32::MIN)),
+        |(min, max), point| (min.min(point), max.max(point)),
+    );
+
+    Rect::from_corners(min, max)
+}
+
+/// A `width` by `height` cell maze centred on the origin, carved with a recursive
+/// backtracker so every cell is reachable. The same seed gives the same maze.
+fn generate_maze(width: usize, height: usize, seed: u64) -> Vec<Boundary> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut east = vec![vec![true; width]; height];
+    let mut south = vec![vec![true; width]; height];
+    let mut visited = vec![vec![false; width]; height];
+    let mut stack = vec![(0, 0)];
+    visited[0][0] = true;
+
+    while let Some(&(col, row)) = stack.last() {
+        let mut neighbours = Vec::with_capacity(4);
+        if col > 0 && !visited[row][col - 1] {
+            neighbours.push((col - 1, row));
+        }
+        if col + 1 < width && !visited[row][col + 1] {
+            neighbours.push((col + 1, row));
+        }
+        if row > 0 && !visited[row - 1][col] {
+            neighbours.push((col, row - 1));
+        }
+        if row + 1 < height && !visited[row + 1][col] {
+            neighbours.push((col, row + 1));
+        }
+
+        if neighbours.is_empty() {
+            stack.pop();
+            continue;
+        }
+
+        let (next_col, next_row) = neighbours[rng.gen_range(0..neighbours.len())];
+
+        // Each cell owns its east and south walls; the wall between two cells
+        // belongs to whichever is further west or north.
+        match (next_col.cmp(&col), next_row.cmp(&row)) {
+            (Ordering::Less, _) => east[row][next_col] = false,
+            (Ordering::Greater, _) => east[row][col] = false,
+            (_, Ordering::Less) => south[next_row][col] = false,
+            _ => south[row][col] = false,
+        }
+
+        visited[next_row][next_col] = true;
+        stack.push((next_col, next_row));
+    }
+
+    let top_left = pt2(
+        -(width as f32) * MAZE_CELL_SIZE / 2.0,
+        height as f32 * MAZE_CELL_SIZE / 2.0,
+    );
+    let corner =
+        |col: usize, row: usize| top_left + vec2(col as f32, -(row as f32)) * MAZE_CELL_SIZE;
+
+    let mut boundaries = vec![
+        Boundary::new(corner(0, 0), corner(width, 0)),
+        Boundary::new(corner(0, 0), corner(0, height)),
+    ];
+
+    for row in 0..height {
+        for col in 0..width {
+            if east[row][col] {
+                boundaries.push(Boundary::new(
+                    corner(col + 1, row),
+                    corner(col + 1, row + 1),
+                ));
+            }
+            if south[row][col] {
+                boundaries.push(Boundary::new(
+                    corner(col, row + 1),
+                    corner(col + 1, row + 1),
+                ));
+            }
+        }
+    }
+
+    boundaries
+}
+
+impl Model {
+    fn update_toggles(&mut self, key: Key) {
+        match key {
+            _ if key == self.bindings.cycle_view => self.view_mode = self.view_mode.next(),
+            Key::G => self.use_grid = !self.use_grid,
+            Key::F => self.show_fps = !self.show_fps,
+            Key::F3 => self.show_debug = !self.show_debug,
+            Key::F4 => self.fisheye_correction = !self.fisheye_correction,
+            Key::M => self.show_minimap = !self.show_minimap,
+            Key::X => self.show_crosshair = !self.show_crosshair,
+            Key::O => self.show_outlines = !self.show_outlines,
+            Key::B => self.show_wireframe = !self.show_wireframe,
+            Key::Y => self.render_scale = next_render_scale(self.render_scale),
+            Key::F10 => self.stable_columns = !self.stable_columns,
+            Key::H => self.show_compass = !self.show_compass,
+            Key::N => self.act(RecordedAction::ToggleNoclip),
+            Key::Escape => self.cursor_grab = !self.cursor_grab,
+            Key::Tab => self.paused = !self.paused,
+            Key::Minus if self.key_edges.any_held(&[Key::LShift, Key::RShift]) => {
+                self.ray_count = next_ray_count(
+                    self.ray_count,
+                    self.column_angles.columns.len() as i32,
+                    false,
+                )
+            }
+            Key::Equals if self.key_edges.any_held(&[Key::LShift, Key::RShift]) => {
+                self.ray_count = next_ray_count(
+                    self.ray_count,
+                    self.column_angles.columns.len() as i32,
+                    true,
+                )
+            }
+            Key::Minus => {
+                self.resolution = (self.resolution - 1).clamp(MIN_RESOLUTION, MAX_RESOLUTION)
+            }
+            Key::Equals => {
+                self.resolution = (self.resolution + 1).clamp(MIN_RESOLUTION, MAX_RESOLUTION)
+            }
+            Key::F5 => match save_scene(self, SCENE_PATH) {
+                Ok(()) => self.unsaved_edits = false,
+                Err(err) => eprintln!("Failed to save scene: {}", err),
+            },
+            Key::F6 => self.toggle_recording(),
+            Key::F7 => self.toggle_playback(),
+            Key::F8 => match export_map(&self.boundaries, EXPORT_PATH) {
+                Ok(()) => println!("Exported walls to {}", EXPORT_PATH),
+                Err(err) => eprintln!("Failed to export map: {}", err),
+            },
+            Key::C => self.edit_start = None,
+            Key::T => self.snap_to_grid = !self.snap_to_grid,
+            Key::E => self.act(RecordedAction::UseDoor),
+            Key::R => self.next_maze(),
+            Key::Back => self.remove_last_boundary(),
+            Key::Key0 if self.key_edges.any_held(&[Key::LControl, Key::RControl]) => {
+                self.act(RecordedAction::ToggleLayer(0))
+            }
+            Key::Key1
+            | Key::Key2
+            | Key::Key3
+            | Key::Key4
+            | Key::Key5
+            | Key::Key6
+            | Key::Key7
+            | Key::Key8
+            | Key::Key9 => {
+                let digit = key as usize - Key::Key1 as usize + 1;
+
+                if self.key_edges.any_held(&[Key::LControl, Key::RControl]) {
+                    self.act(RecordedAction::ToggleLayer(digit as u32));
+                } else {
+                    self.use_spawn(digit - 1);
+                }
+            }
+            Key::F9 if self.edits_locked() => {}
+            Key::F9 => match load_scene(SCENE_PATH) {
+                Ok(scene) => {
+                    self.apply_scene(scene);
+                    self.unsaved_edits = false;
+                }
+                Err(err) => eprintln!("Failed to load scene: {}", err),
+            },
+            _ => {}
+        }
+    }
+
+    fn world(&self) -> World<'_> {
+        World {
+            boundaries: &self.boundaries,
+            hidden_layers: &self.hidden_layers,
+            circles: &self.circles,
+            doors: &self.doors,
+            portals: &self.portals,
+            index: &self.index,
+        }
+    }
+
+    fn toggle_layer(&mut self, layer: u32) {
+        if !self.hidden_layers.remove(&layer) {
+            self.hidden_layers.insert(layer);
+        }
+    }
+
+    // Shift stores where the player is, printed in map syntax so it can be kept.
+    fn use_spawn(&mut self, slot: usize) {
+        if self.key_edges.any_held(&[Key::LShift, Key::RShift]) {
+            let spawn = Spawn::of(&self.player);
+            println!(
+                "Stored spawn {}: spawn {},{} {}",
+                slot + 1,
+                spawn.pos.x,
+                spawn.pos.y,
+                spawn.look_dir.angle().to_degrees()
+            );
+            self.spawns[slot] = Some(spawn);
+        } else if let Some(spawn) = self.spawns[slot] {
+            self.act(RecordedAction::UseSpawn(spawn));
+        }
+    }
+
+    // Live actions are ignored during playback, which replays the recorded ones.
+    fn act(&mut self, action: RecordedAction) {
+        if self.playback.is_some() {
+            return;
+        }
+        if let Some(record) = &mut self.record {
+            record.pending.push(action);
+        }
+
+        self.perform(action);
+    }
+
+    fn perform(&mut self, action: RecordedAction) {
+        match action {
+            RecordedAction::UseDoor => self.use_door(),
+            RecordedAction::ToggleNoclip => self.noclip = !self.noclip,
+            RecordedAction::ToggleLayer(layer) => self.toggle_layer(layer),
+            RecordedAction::UseSpawn(spawn) => spawn.apply(&mut self.player),
+            RecordedAction::LookAt(target) => self.player.look_at(target),
+        }
+    }
+
+    // Recordings don't capture the walls, so they can't change under one.
+    fn edits_locked(&self) -> bool {
+        let locked = self.record.is_some() || self.playback.is_some();
+
+        if locked {
+            eprintln!("Walls can't be changed while recording or playing back");
+        }
+        locked
+    }
+
+    fn use_door(&mut self) {
+        let player = &self.player;
+
+        let nearest = self
+            .doors
+            .iter_mut()
+            .map(|door| {
+                let distance =
+                    (player.pos - door.closest_point(player.pos, door.boundary.length)).length();
+                (distance, door)
+            })
+            .filter(|(distance, _)| *distance < USE_DISTANCE)
+            .min_by(|(a, _), (b, _)| a.total_cmp(b));
+
+        if let Some((_, door)) = nearest {
+            door.toggle();
+        }
+    }
+
+    /// Where a window position lands in the world under the debug camera.
+    fn debug_to_world(&self, screen: Point2) -> Point2 {
+        (screen - self.cam_offset) / self.cam_zoom
+    }
+
+    /// Where a clicked world point ends up, after any grid snapping.
+    fn snapped(&self, point: Point2) -> Point2 {
+        if self.snap_to_grid {
+            (point / self.snap_size).round() * self.snap_size
+        } else {
+            point
+        }
+    }
+
+    /// Zooms the debug camera by `steps` wheel notches, keeping the point under
+    /// the cursor where it is.
+    fn zoom_debug(&mut self, cursor: Point2, steps: f32) {
+        let anchor = self.debug_to_world(cursor);
+
+        self.cam_zoom = (self.cam_zoom * DEBUG_ZOOM_STEP.powf(steps))
+            .clamp(DEBUG_ZOOM_RANGE.0, DEBUG_ZOOM_RANGE.1);
+        self.cam_offset = cursor - anchor * self.cam_zoom;
+    }
+
+    fn place_boundary_point(&mut self, point: Point2) {
+        if self.edits_locked() {
+            return;
+        }
+
+        match self.edit_start.take() {
+            Some(start) => {
+                if start != point {
+                    self.boundaries.push(Boundary::new(start, point));
+                    self.boundaries_changed();
+                    self.unsaved_edits = true;
+                }
+            }
+            None => self.edit_start = Some(point),
+        }
+    }
+
+    fn remove_last_boundary(&mut self) {
+        if self.edits_locked() {
+            return;
+        }
+
+        if let Some(last) = self
+            .boundaries
+            .iter()
+            .rposition(|boundary| !boundary.window_edge)
+        {
+            self.boundaries.remove(last);
+            self.boundaries_changed();
+            self.unsaved_edits = true;
+        }
+    }
+
+    // Replaces the level with a fresh maze and puts the player in its first cell.
+    // The maze replaces the whole level, so unsaved edits are autosaved first, as on
+    // quit, and left alone if that fails. The map file is no longer watched, or its
+    // next change would swap the maze back out.
+    fn next_maze(&mut self) {
+        if self.edits_locked() {
+            return;
+        }
+
+        if self.unsaved_edits {
+            if let Err(err) = autosave(self) {
+                eprintln!("Not replacing unsaved edits with a maze: {}", err);
+                return;
+            }
+            self.unsaved_edits = false;
+        }
+
+        self.maze_seed += 1;
+        self.boundaries = generate_maze(MAZE_SIZE.0, MAZE_SIZE.1, self.maze_seed);
+        self.circles.clear();
+        self.doors.clear();
+        self.portals.clear();
+        self.lights.clear();
+        self.spawns = [None; SPAWN_SLOTS];
+        self.sprites.clear();
+        self.edit_start = None;
+        self.map_watch = None;
+        self.map_error = None;
+
+        let half_extent = vec2(MAZE_SIZE.0 as f32, MAZE_SIZE.1 as f32) * MAZE_CELL_SIZE / 2.0;
+        self.player = Player::new();
+        self.player.pos =
+            pt2(-half_extent.x, half_extent.y) + vec2(MAZE_CELL_SIZE, -MAZE_CELL_SIZE) / 2.0;
+
+        self.boundaries_changed();
+        self.explored = ExploredMap::new(self.bounds);
+    }
+
+    // Everything that moves on its own or from held input; skipped while paused.
+    fn advance(&mut self, dt: f32) {
+        // Playback stands in for both the live input and the frame time, so the run
+        // goes exactly as it was recorded.
+        let dt = match self
+            .playback
+            .as_mut()
+            .map(|playback| playback.frames.pop_front())
+        {
+            Some(Some(frame)) => {
+                self.moves = frame.moves;
+                for action in frame.actions {
+                    self.perform(action);
+                }
+                frame.dt
+            }
+            Some(None) => {
+                println!("Playback finished");
+                self.playback = None;
+                self.moves = Moves::new();
+                dt
+            }
+            None => dt,
+        };
+
+        self.time += dt;
+
+        if let (Some(gamepad), None) = (&self.gamepad, &self.playback) {
+            self.moves.update_gamepad(gamepad.axes());
+        }
+
+        if let Some(record) = &mut self.record {
+            record.frames.push(RecordedFrame {
+                dt,
+                moves: self.moves,
+                actions: std::mem::take(&mut record.pending),
+            });
+        }
+
+        let look = self.moves.mouse_look;
+        self.player.turn(look.x);
+        self.player.update_player_pitch(look.y);
+        self.moves.mouse_look = Vec2::ZERO;
+
+        let time = self.time;
+        let mut moved = false;
+        for boundary in &mut self.boundaries {
+            moved |= boundary.animate(time);
+        }
+        if moved {
+            self.index = BoundaryIndex::build(&self.boundaries);
+            self.dirty = true;
+        }
+
+        // A door that stops this frame still moved on the way.
+        let doors_were_moving = self.doors.iter().any(Door::is_moving);
+        for door in &mut self.doors {
+            door.update(dt, &self.player);
+        }
+        if doors_were_moving || self.doors.iter().any(Door::is_moving) {
+            self.dirty = true;
+        }
+
+        let world = World {
+            boundaries: &self.boundaries,
+            hidden_layers: &self.hidden_layers,
+            circles: &self.circles,
+            doors: &self.doors,
+            portals: &self.portals,
+            index: &self.index,
+        };
+
+        let steps =
+            self.moves
+                .update_player(&mut self.player, world, &self.movement, self.noclip, dt);
+        for split in &mut self.split_players {
+            split
+                .moves
+                .update_player(&mut split.player, world, &self.movement, self.noclip, dt);
+
+            if !self.noclip {
+                split.player.clamp_to(self.bounds);
+            }
+        }
+        for _ in 0..steps {
+            self.on_step();
+        }
+
+        if !self.noclip {
+            self.player.clamp_to(self.bounds);
+        }
+        self.reveal_view();
+        self.target_fov = self.moves.update_fov(self.target_fov, dt);
+        self.update_zoom(dt);
+    }
+
+    // Called once per footstep, which is where footstep sounds would be played.
+    // `loudness` follows how fast the player is going, from 0 up to 1 at full speed.
+    fn on_step(&mut self) {
+        let loudness = (self.player.velocity.length() / self.movement.move_speed).min(1.0);
+
+        self.footsteps += 1;
+        self.step_loudness = loudness;
+    }
+
+    // Adds a player, starting where the first one is standing, until every set of
+    // split-screen keys is in use; the next press goes back to a single view.
+    // Recordings only hold the first player's input, so the split can't change
+    // while one is being made or played.
+    fn toggle_split_screen(&mut self, window: Rect) {
+        if self.record.is_some() || self.playback.is_some() {
+            eprintln!("Split screen can't change while recording or playing back");
+            return;
+        }
+
+        let mut bindings = KeyBindings::split_players();
+
+        if self.split_players.len() == bindings.len() {
+            self.split_players.clear();
+            return;
+        }
+
+        let width = window.w() / (self.split_players.len() + 2) as f32;
+        self.split_players.push(SplitPlayer {
+            player: self.player.clone(),
+            moves: Moves::new(),
+            bindings: bindings.swap_remove(self.split_players.len()),
+            angles: ColumnAngles::new(self.fov, self.resolution, width),
+            columns: Vec::new(),
+        });
+    }
+
+    // Frame-rate independent easing, clamped so it never overshoots either end.
+    fn update_zoom(&mut self, dt: f32) {
+        let goal = if self.moves.zoom {
+            self.zoom_fov
+        } else {
+            self.target_fov
+        };
+        let eased = self.fov + (goal - self.fov) * (1.0 - (-self.zoom_speed * dt).exp());
+
+        self.fov = eased.clamp(goal.min(self.fov), goal.max(self.fov));
+    }
+
+    // Starts recording, or stops and writes out the recording so far.
+    fn toggle_recording(&mut self) {
+        match self.record.take() {
+            Some(record) => match record.save(RECORDING_PATH) {
+                Ok(()) => println!("Saved {} frames to {}", record.frames.len(), RECORDING_PATH),
+                Err(err) => eprintln!("Failed to save recording: {}", err),
+            },
+            None if !self.split_players.is_empty() => {
+                eprintln!("Recordings hold one player; leave split screen first")
+            }
+            None => {
+                self.record = Some(Recorder::start(
+                    &mut self.player,
+                    self.time,
+                    self.noclip,
+                    &self.hidden_layers,
+                    &self.doors,
+                ))
+            }
+        }
+    }
+
+    fn toggle_playback(&mut self) {
+        if self.playback.take().is_some() {
+            self.moves = Moves::new();
+            return;
+        }
+
+        if !self.split_players.is_empty() {
+            eprintln!("Recordings hold one player; leave split screen first");
+            return;
+        }
+
+        match Playback::load(RECORDING_PATH) {
+            Ok((start, _)) if start.doors.len() != self.doors.len() => {
+                eprintln!("Failed to load recording: it was made on a map with different doors")
+            }
+            Ok((start, playback)) => {
+                self.player = start.player;
+                self.time = start.time;
+                self.noclip = start.noclip;
+                self.hidden_layers = start.hidden_layers;
+                for (door, (open_amount, state)) in self.doors.iter_mut().zip(start.doors) {
+                    door.open_amount = open_amount;
+                    door.state = state;
+                }
+                self.playback = Some(playback);
+            }
+            Err(err) => eprintln!("Failed to load recording: {}", err),
+        }
+    }
+
+    // A coarse fan of rays across the view is plenty to find what can be seen;
+    // misses reveal out to where the fog hides everything.
+    fn reveal_view(&mut self) {
+        let fov = self.fov.to_radians();
+
+        for i in 0..EXPLORE_RAYS {
+            let angle = ((i as f32 + 0.5) / EXPLORE_RAYS as f32 - 0.5) * fov;
+            let ray = if self.use_grid {
+                Ray::cast_grid(&self.player, angle, &self.grid, self.max_distance).0
+            } else {
+                Ray::cast(&self.player, angle, self.world(), 0, self.max_distance)
+            };
+            let reach = self.fog.end.min(self.max_distance);
+            let end = ray.end.unwrap_or(ray.origin + reach * ray.dir);
+
+            self.explored.reveal_along(ray.origin, end);
+        }
+    }
+
+    fn boundaries_changed(&mut self) {
+        self.dirty = true;
+        self.index = BoundaryIndex::build(&self.boundaries);
+        self.bounds = level_bounds(&self.boundaries, &self.grid);
+        self.minimap.fit(&self.boundaries);
+    }
+
+    fn apply_scene(&mut self, scene: Scene) {
+        self.player = scene.player;
+        self.fov = scene.fov;
+        self.target_fov = scene.fov;
+        self.resolution = scene.resolution;
+        self.boundaries = scene.boundaries;
+        self.circles = scene.circles;
+        self.doors = scene.doors;
+        self.portals = scene.portals;
+        self.lights = scene.lights;
+        self.spawns = scene.spawns;
+        self.edit_start = None;
+        self.boundaries_changed();
+        self.explored = ExploredMap::new(self.bounds);
+    }
+
+    // Everything about the cameras that changes what they see.
+    fn camera_state(&self) -> (f32, [f32; 6], Vec<[f32; 6]>) {
+        (
+            self.fov,
+            self.player.camera_state(),
+            self.split_players
+                .iter()
+                .map(|split| split.player.camera_state())
+                .collect(),
+        )
+    }
+
+    // The top-down view draws the level itself, so there is nothing to cast.
+    fn recast(&mut self) {
+        let top_down = self.view_mode == ViewMode::TopDown;
+
+        let columns = if top_down {
+            Vec::new()
+        } else {
+            cast_view(self, &self.player, &self.column_angles)
+        };
+        let split_columns: Vec<Vec<ColumnResult>> = self
+            .split_players
+            .iter()
+            .map(|split| {
+                if top_down {
+                    Vec::new()
+                } else {
+                    cast_view(self, &split.player, &split.angles)
+                }
+            })
+            .collect();
+
+        self.columns = columns;
+        for (split, columns) in self.split_players.iter_mut().zip(split_columns) {
+            split.columns = columns;
+        }
+        self.dirty = false;
+    }
+
+    // A change made while recording or playing back is picked up once that stops.
+    // Unsaved editor changes are autosaved before the reload replaces them; if that
+    // fails the file is left unloaded rather than losing them.
+    fn poll_map(&mut self, dt: f32) {
+        if self.record.is_some() || self.playback.is_some() {
+            return;
+        }
+
+        let path = match &mut self.map_watch {
+            Some(watch) => {
+                if !watch.changed(dt) {
+                    return;
+                }
+                watch.path.clone()
+            }
+            None => return,
+        };
+
+        if self.unsaved_edits {
+            if let Err(err) = autosave(self) {
+                eprintln!("Not reloading {} over unsaved edits: {}", path, err);
+                self.map_error = Some(format!("unsaved edits kept: {}", err));
+                return;
+            }
+        }
+
+        match self.reload_map(&path) {
+            Ok(()) => {
+                println!("Reloaded {}", path);
+                self.map_error = None;
+                self.unsaved_edits = false;
+            }
+            Err(err) => {
+                eprintln!("Failed to reload map: {}", err);
+                self.map_error = Some(err.to_string());
+            }
+        }
+    }
+
+    // Everything is parsed before anything is replaced, so a broken file leaves the
+    // level as it was. The player stays put, pushed out of any wall that now
+    // overlaps them.
+    fn reload_map(&mut self, path: &str) -> io::Result<()> {
+        let map = MapFile::load(path)?;
+
+        self.boundaries = map.boundaries;
+        self.circles = map.circles;
+        self.doors = map.doors;
+        self.portals = map.portals;
+        self.lights = map.lights;
+        self.spawns = map.spawns;
+        self.edit_start = None;
+        self.boundaries_changed();
+
+        if self.explored.bounds != self.bounds {
+            self.explored = ExploredMap::new(self.bounds);
+        }
+
+        let world = World {
+            boundaries: &self.boundaries,
+            hidden_layers: &self.hidden_layers,
+            circles: &self.circles,
+            doors: &self.doors,
+            portals: &self.portals,
+            index: &self.index,
+        };
+        self.player.resolve_collisions(world);
+        self.player.clamp_to(self.bounds);
+
+        Ok(())
+    }
+
+    fn resize(&mut self, rect: Rect) {
+        let had_edges = self.boundaries.iter().any(|boundary| boundary.window_edge);
+
+        if had_edges {
+            self.boundaries.retain(|boundary| !boundary.window_edge);
+            self.boundaries.extend(Boundary::window_edges(rect));
+            self.index = BoundaryIndex::build(&self.boundaries);
+            self.bounds = level_bounds(&self.boundaries, &self.grid);
+        }
+
+        self.minimap = MiniMap::new(rect, &self.boundaries);
+    }
+
+    fn record_frame_time(&mut self, seconds: f32) {
+        if self.frame_times.len() == FRAME_SAMPLES {
+            self.frame_times.pop_front();
+        }
+        self.frame_times.push_back(seconds);
+    }
+
+    fn fps(&self) -> f32 {
+        let total: f32 = self.frame_times.iter().sum();
+
+        if total > 0.0 {
+            self.frame_times.len() as f32 / total
+        } else {
+            0.0
+        }
+    }
+}
+
+// Whether neighbouring columns at these depths get an outline between them. A miss
+// is infinitely deep, so two misses side by side are the same opening, not an edge.
+fn is_outline_edge(a: f32, b: f32, threshold: f32) -> bool {
+    if !a.is_finite() && !b.is_finite() {
+        return false;
+    }
+
+    (a - b).abs() > threshold
+}
+
+fn take_screenshot(app: &App) {
+    if let Err(err) = fs::create_dir_all(SCREENSHOT_DIR) {
+        eprintln!("Failed to create {}: {}", SCREENSHOT_DIR, err);
+        return;
+    }
+
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_millis());
+
+    app.main_window()
+        .capture_frame(format!("{}/screenshot-{}.png", SCREENSHOT_DIR, timestamp));
+}
+
+impl Recorder {
+    // The parts of the player that aren't saved are reset here, so the recorded run
+    // and its playback start from the same state.
+    fn start(
+        player: &mut Player,
+        time: f32,
+        noclip: bool,
+        hidden_layers: &HashSet<u32>,
+        doors: &[Door],
+    ) -> Recorder {
+        player.target_look_dir = player.look_dir;
+        player.velocity = Vec2::ZERO;
+        player.bob_phase = 0.0;
+        player.bob_amount = 0.0;
+
+        Recorder {
+            start: RecordingStart {
+                player: player.clone(),
+                time,
+                noclip,
+                hidden_layers: hidden_layers.clone(),
+                doors: doors
+                    .iter()
+                    .map(|door| (door.open_amount, door.state))
+                    .collect(),
+            },
+            frames: Vec::new(),
+            pending: Vec::new(),
+        }
+    }
+
+    // One JSON value per line: the start, then a frame per line.
+    fn save(&self, path: &str) -> io::Result<()> {
+        let mut lines = vec![serde_json::to_string(&self.start)?];
+
+        for frame in &self.frames {
+            lines.push(serde_json::to_string(frame)?);
+        }
+
+        fs::write(path, lines.join("\n") + "\n")
+    }
+}
+
+impl Playback {
+    fn load(path: &str) -> io::Result<(RecordingStart, Playback)> {
+        let contents = fs::read_to_string(path)?;
+        let mut lines = contents.lines().filter(|line| !line.trim().is_empty());
+
+        let start: RecordingStart = match lines.next() {
+            Some(line) => serde_json::from_str(line)?,
+            None => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{}: empty recording", path),
+                ))
+            }
+        };
+        let frames = lines
+            .map(serde_json::from_str)
+            .collect::<Result<VecDeque<RecordedFrame>, _>>()?;
+
+        Ok((start, Playback { frames }))
+    }
+}
+
+fn save_scene(model: &Model, path: &str) -> io::Result<()> {
+    let scene = Scene {
+        player: model.player.clone(),
+        fov: model.target_fov,
+        resolution: model.resolution,
+        boundaries: model.boundaries.clone(),
+        circles: model.circles.clone(),
+        doors: model.doors.clone(),
+        portals: model.portals.clone(),
+        lights: model.lights.clone(),
+        spawns: model.spawns,
+    };
+
+    let json = serde_json::to_string_pretty(&scene)?;
+    fs::write(path, json)
+}
+
+// Writes the walls in the text map format, leaving out the window edges: they're
+// generated to fit the window rather than part of the level.
+fn export_map(boundaries: &[Boundary], path: &str) -> io::Result<()> {
+    let lines: Vec<String> = boundaries
+        .iter()
+        .filter(|boundary| !boundary.window_edge)
+        .map(Boundary::to_map_line)
+        .collect();
+
+    fs::write(path, lines.join("\n") + "\n")
+}
+
+// Scenes are checked against the same limits as the command line, and textures,
+// which aren't saved, are loaded again from the paths the walls keep.
+fn load_scene(path: &str) -> io::Result<Scene> {
+    let json = fs::read_to_string(path)?;
+    let mut scene: Scene = serde_json::from_str(&json)?;
+    let invalid =
+        |msg: String| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path, msg));
+
+    if !fov_in_range(scene.fov) {
+        return Err(invalid(format!(
+            "invalid fov `{}`, expected {} to {}",
+            scene.fov, MIN_FOV, MAX_FOV
+        )));
+    }
+    if !resolution_in_range(scene.resolution) {
+        return Err(invalid(format!(
+            "invalid resolution `{}`, expected {} to {}",
+            scene.resolution, MIN_RESOLUTION, MAX_RESOLUTION
+        )));
+    }
+    if scene.player.look_dir.length() == 0.0 {
+        return Err(invalid("invalid look direction".to_string()));
+    }
+
+    let mut textures: HashMap<String, Arc<RgbaImage>> = HashMap::new();
+    let boundaries = scene
+        .boundaries
+        .iter_mut()
+        .chain(scene.doors.iter_mut().map(|door| &mut door.boundary))
+        .chain(
+            scene
+                .portals
+                .iter_mut()
+                .flat_map(|portal| [&mut portal.a, &mut portal.b]),
+        );
+    for boundary in boundaries {
+        if let Some(texture_path) = &boundary.texture_path {
+            if !textures.contains_key(texture_path) {
+                let image = load_texture(texture_path).map_err(invalid)?;
+                textures.insert(texture_path.clone(), Arc::new(image));
+            }
+            boundary.texture = textures.get(texture_path).cloned();
+        }
+    }
+
+    Ok(scene)
+}
+
+fn parse_key(name: &str) -> Option<Key> {
+    let key = match name.to_ascii_uppercase().as_str() {
+        "A" => Key::A,
+        "B" => Key::B,
+        "C" => Key::C,
+        "D" => Key::D,
+        "E" => Key::E,
+        "F" => Key::F,
+        "G" => Key::G,
+        "H" => Key::H,
+        "I" => Key::I,
+        "J" => Key::J,
+        "K" => Key::K,
+        "L" => Key::L,
+        "M" => Key::M,
+        "N" => Key::N,
+        "O" => Key::O,
+        "P" => Key::P,
+        "Q" => Key::Q,
+        "R" => Key::R,
+        "S" => Key::S,
+        "T" => Key::T,
+        "U" => Key::U,
+        "V" => Key::V,
+        "W" => Key::W,
+        "X" => Key::X,
+        "Y" => Key::Y,
+        "Z" => Key::Z,
+        "0" => Key::Key0,
+        "1" => Key::Key1,
+        "2" => Key::Key2,
+        "3" => Key::Key3,
+        "4" => Key::Key4,
+        "5" => Key::Key5,
+        "6" => Key::Key6,
+        "7" => Key::Key7,
+        "8" => Key::Key8,
+        "9" => Key::Key9,
+        "UP" => Key::Up,
+        "DOWN" => Key::Down,
+        "LEFT" => Key::Left,
+        "RIGHT" => Key::Right,
+        "SPACE" => Key::Space,
+        "LSHIFT" => Key::LShift,
+        "RSHIFT" => Key::RShift,
+        "LCONTROL" => Key::LControl,
+        "RCONTROL" => Key::RControl,
+        "LALT" => Key::LAlt,
+        "RALT" => Key::RAlt,
+        "TAB" => Key::Tab,
+        "COMMA" => Key::Comma,
+        "PERIOD" => Key::Period,
+        "SEMICOLON" => Key::Semicolon,
+        "APOSTROPHE" => Key::Apostrophe,
+        "NUMPAD8" => Key::Numpad8,
+        "NUMPAD4" => Key::Numpad4,
+        "NUMPAD5" => Key::Numpad5,
+        "NUMPAD6" => Key::Numpad6,
+        _ => return None,
+    };
+
+    Some(key)
+}
+
+fn fov_in_range(fov: f32) -> bool {
+    (MIN_FOV..=MAX_FOV).contains(&fov)
+}
+
+fn resolution_in_range(resolution: i32) -> bool {
+    (MIN_RESOLUTION..=MAX_RESOLUTION).contains(&resolution)
+}
+
+/// Startup settings from the command line.
+struct Options {
+    fov: f32,
+    resolution: i32,
+    /// A `.png` is loaded as a tile grid, anything else as a text map.
+    map: String,
+    /// Whether `map` came from the command line. Only the default map may be
+    /// missing; a named one that can't be loaded is an error.
+    map_given: bool,
+    fullscreen: bool,
+    compass: Corner,
+    aa_samples: u32,
+    /// Rays cast across the view, or 0 for one per column.
+    ray_count: i32,
+    /// Distance walked per footstep; always positive, or steps would never end.
+    stride: f32,
+    /// Print one frame as ASCII art and exit instead of opening a window.
+    headless: bool,
+}
+
+impl Options {
+    fn from_args(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+        let mut options = Options {
+            fov: 60.0,
+            resolution: 5,
+            map: MAP_PATH.to_string(),
+            map_given: false,
+            fullscreen: false,
+            compass: Corner::BottomLeft,
+            aa_samples: 1,
+            ray_count: 0,
+            stride: MovementConfig::new().stride,
+            headless: false,
+        };
+
+        while let Some(arg) = args.next() {
+            let mut value = |name: &str| {
+                args.next()
+                    .ok_or_else(|| format!("missing value for `{}`", name))
+            };
+
+            match arg.as_str() {
+                "--fov" => {
+                    let text = value("--fov")?;
+                    options.fov = text
+                        .parse::<f32>()
+                        .ok()
+                        .filter(|&fov| fov_in_range(fov))
+                        .ok_or_else(|| {
+                            format!(
+                                "invalid fov `{}`, expected {} to {}",
+                                text, MIN_FOV, MAX_FOV
+                            )
+                        })?;
+                }
+                "--resolution" => {
+                    let text = value("--resolution")?;
+                    options.resolution = text
+                        .parse::<i32>()
+                        .ok()
+                        .filter(|&resolution| resolution_in_range(resolution))
+                        .ok_or_else(|| {
+                            format!(
+                                "invalid resolution `{}`, expected {} to {}",
+                                text, MIN_RESOLUTION, MAX_RESOLUTION
+                            )
+                        })?;
+                }
+                "--map" => {
+                    options.map = value("--map")?;
+                    options.map_given = true;
+                }
+                "--fullscreen" => options.fullscreen = true,
+                "--compass" => {
+                    let text = value("--compass")?;
+                    options.compass = Corner::from_name(&text).ok_or_else(|| {
+                        format!(
+                            "invalid corner `{}`, expected top-left, top-right, bottom-left or bottom-right",
+                            text
+                        )
+                    })?;
+                }
+                "--aa" => {
+                    let text = value("--aa")?;
+                    options.aa_samples = text
+                        .parse::<u32>()
+                        .ok()
+                        .filter(|&samples| samples >= 1)
+                        .ok_or_else(|| {
+                        format!("invalid aa samples `{}`, expected at least 1", text)
+                    })?;
+                }
+                "--rays" => {
+                    let text = value("--rays")?;
+                    options.ray_count = text
+                        .parse::<i32>()
+                        .ok()
+                        .filter(|count| (MIN_RAY_COUNT..=MAX_RAY_COUNT).contains(count))
+                        .ok_or_else(|| {
+                            format!(
+                                "invalid ray count `{}`, expected {} to {}",
+                                text, MIN_RAY_COUNT, MAX_RAY_COUNT
+                            )
+                        })?;
+                }
+                "--stride" => {
+                    let text = value("--stride")?;
+                    options.stride = text
+                        .parse::<f32>()
+                        .ok()
+                        .filter(|stride| stride.is_finite() && *stride > 0.0)
+                        .ok_or_else(|| {
+                            format!("invalid stride `{}`, expected a positive length", text)
+                        })?;
+                }
+                "--headless" => options.headless = true,
+                _ if arg.starts_with("--") => return Err(format!("unknown option `{}`", arg)),
+                _ => {
+                    options.map = arg;
+                    options.map_given = true;
+                }
+            }
+        }
+
+        Ok(options)
+    }
+}
+
+// Renders the map from the default player position as ASCII art, nearer walls in
+// denser characters. Unlike the windowed app, a map that fails to load is an
+// error, so this doubles as a smoke test for map files.
+fn run_headless(options: &Options) -> io::Result<()> {
+    let (cols, rows) = ASCII_SIZE;
+    let player = Player::new();
+    let angles = ColumnAngles::new(options.fov, 1, cols as f32);
+
+    let columns = if options.map.ends_with(".png") {
+        let grid = Grid::from_image(&options.map)?;
+        cast_grid_scene(&player, &grid, &angles, MAX_DISTANCE, 1)
+    } else {
+        let map = MapFile::load(&options.map)?;
+        let index = BoundaryIndex::build(&map.boundaries);
+        let world = World {
+            boundaries: &map.boundaries,
+            hidden_layers: &HashSet::new(),
+            circles: &map.circles,
+            doors: &map.doors,
+            portals: &map.portals,
+            index: &index,
+        };
+
+        cast_scene(&player, world, &angles, 4, MAX_DISTANCE, 1)
+    };
+
+    let projection = projection_distance(options.fov, cols as f32);
+    let screen_height = rows as f32 * 2.0;
+    // Each column's bottom edge and height in pixels relative to the horizon. Walls
+    // stand on the floor, as in the 3D view, and a miss has no height at all.
+    let spans: Vec<(f32, f32)> = columns
+        .iter()
+        .map(|column| {
+            let full = projected_wall_height(column.depth, projection, DEFAULT_NEAR_CLIP);
+            (-full / 2.0, full * column.ray.height)
+        })
+        .collect();
+
+    for row in 0..rows {
+        // Pixels above the horizon, at the middle of this row.
+        let y = (rows as f32 / 2.0 - row as f32 - 0.5) * 2.0;
+
+        let line: String = spans
+            .iter()
+            .take(cols)
+            .map(|&(bottom, height)| {
+                if y >= bottom && y < bottom + height {
+                    match height / screen_height {
+                        t if t >= 1.0 => '@',
+                        t if t >= 0.6 => '#',
+                        t if t >= 0.35 => '=',
+                        t if t >= 0.2 => '-',
+                        _ => ':',
+                    }
+                } else if y < 0.0 {
+                    '.'
+                } else {
+                    ' '
+                }
+            })
+            .collect();
+
+        println!("{}", line);
+    }
+
+    Ok(())
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    if args.iter().any(|arg| arg == "--help" || arg == "-h") {
+        println!("{}", USAGE);
+        return;
+    }
+
+    match Options::from_args(args.into_iter()) {
+        Ok(options) if options.headless => {
+            if let Err(err) = run_headless(&options) {
+                eprintln!("Failed to render {}: {}", options.map, err);
+                process::exit(1);
+            }
+            return;
+        }
+        Ok(options) => {
+            let _ = OPTIONS.set(options);
+        }
+        Err(msg) => {
+            eprintln!("{}\n{}", msg, USAGE);
+            process::exit(2);
+        }
+    }
+
+    nannou::app(model)
+        .event(event)
+        .update(update)
+        .simple_window(view)
+        .run();
+}
+
+fn model(app: &App) -> Model {
+    let options = OPTIONS
+        .get()
+        .expect("options are parsed before the app starts");
+
+    // Escape releases the cursor instead of quitting.
+    app.set_exit_on_escape(false);
+
+    if options.fullscreen {
+        app.main_window().set_fullscreen(true);
+    }
+
+    let map_arg = &options.map;
+    let is_image = map_arg.ends_with(".png");
+    let map_path = if is_image { MAP_PATH } else { map_arg.as_str() };
+
+    // The default map is text, so an image was always named on the command line and
+    // has nothing to fall back to.
+    let (grid, use_grid) = if is_image {
+        match Grid::from_image(map_arg) {
+            Ok(grid) => (grid, true),
+            Err(err) => {
+                eprintln!("Failed to load map image: {}", err);
+                process::exit(1);
+            }
+        }
+    } else {
+        (Grid::from_rect(app.window_rect(), GRID_CELL_SIZE), false)
+    };
+
+    // Only the default map may be missing or broken; a named one has to load.
+    let map = match MapFile::load(map_path) {
+        Ok(map) => map,
+        Err(err) if options.map_given && !is_image => {
+            eprintln!("Failed to load map: {}", err);
+            process::exit(1);
+        }
+        Err(err) => {
+            if err.kind() != io::ErrorKind::NotFound {
+                eprintln!("Failed to load map: {}", err);
+            }
+            MapFile::window_edges(app.window_rect())
+        }
+    };
+    let MapFile {
+        boundaries,
+        circles,
+        doors,
+        portals,
+        lights,
+        spawns,
+    } = map;
+
+    let sprites = match Sprite::from_file(SPRITES_PATH) {
+        Ok(sprites) => sprites,
+        Err(err) => {
+            if err.kind() != io::ErrorKind::NotFound {
+                eprintln!("Failed to load sprites: {}", err);
+            }
+            Vec::new()
+        }
+    };
+
+    let viewmodel = match image::open(VIEWMODEL_PATH) {
+        Ok(image) if image.width() == 0 || image.height() == 0 => {
+            eprintln!("Failed to load viewmodel: {} has no pixels", VIEWMODEL_PATH);
+            None
+        }
+        Ok(image) => Some(wgpu::Texture::from_image(app, &image)),
+        Err(image::ImageError::IoError(err)) if err.kind() == io::ErrorKind::NotFound => None,
+        Err(err) => {
+            eprintln!("Failed to load viewmodel: {}", err);
+            None
+        }
+    };
+
+    #[cfg(target_os = "linux")]
+    let gamepad = match Gamepad::open(GAMEPAD_PATH) {
+        Ok(gamepad) => Some(gamepad),
+        Err(err) => {
+            if err.kind() != io::ErrorKind::NotFound {
+                eprintln!("Failed to open gamepad: {}", err);
+            }
+            None
+        }
+    };
+    #[cfg(not(target_os = "linux"))]
+    let gamepad: Option<Gamepad> = None;
+
+    let bindings = match KeyBindings::from_file(BINDINGS_PATH) {
+        Ok(bindings) => bindings,
+        Err(err) => {
+            if err.kind() != io::ErrorKind::NotFound {
+                eprintln!("Failed to load key bindings: {}", err);
+            }
+            KeyBindings::new()
+        }
+    };
+
+    let model = Model {
+        player: Player::new(),
+        moves: Moves::new(),
+        movement: MovementConfig {
+            stride: options.stride,
+            ..MovementConfig::new()
+        },
+        bindings,
+        key_edges: KeyEdges::default(),
+        sprites,
+        viewmodel,
+        index: BoundaryIndex::build(&boundaries),
+        bounds: level_bounds(&boundaries, &grid),
+        explored: ExploredMap::new(level_bounds(&boundaries, &grid)),
+        minimap: MiniMap::new(app.window_rect(), &boundaries),
+        boundaries,
+        circles,
+        doors,
+        portals,
+        lights,
+        grid,
+        use_grid,
+        resolution: options.resolution,
+        ray_count: options.ray_count,
+        fov: options.fov,
+        target_fov: options.fov,
+        zoom_fov: 20.0,
+        zoom_speed: 10.0,
+        column_angles: ColumnAngles::new(options.fov, options.resolution, app.window_rect().w()),
+        columns: Vec::new(),
+        dirty: true,
+        split_players: Vec::new(),
+        max_bounces: 4,
+        near_clip: DEFAULT_NEAR_CLIP,
+        max_distance: MAX_DISTANCE,
+        fisheye_correction: true,
+        stable_columns: false,
+        render_scale: 1.0,
+        aa_samples: options.aa_samples,
+        view_mode: ViewMode::FirstPerson,
+        sky: SkyGradient {
+            horizon: rgb(0.35, 0.35, 0.4),
+            zenith: rgb(0.12, 0.14, 0.28),
+        },
+        floor_color: rgb(0.45, 0.35, 0.25),
+        fog: FogConfig {
+            color: rgb(0.2, 0.2, 0.22),
+            start: 400.0,
+            end: 1500.0,
+        },
+        mouse_sensitivity: 0.003,
+        shading_gamma: 1.0,
+        ambient: DEFAULT_AMBIENT,
+        normal_shading: 0.25,
+        side_shading: 1.0,
+        frame_times: VecDeque::with_capacity(FRAME_SAMPLES),
+        show_fps: false,
+        show_debug: false,
+        show_minimap: false,
+        crosshair: Crosshair {
+            color: rgb(1.0, 1.0, 1.0),
+            size: 8.0,
+        },
+        show_crosshair: true,
+        outlines: Outlines {
+            threshold: 40.0,
+            color: rgb(0.05, 0.05, 0.05),
+        },
+        show_outlines: false,
+        show_wireframe: false,
+        line_weights: LineWeights {
+            boundary: 4.0,
+            ray: 1.0,
+            player: 10.0,
+        },
+        cam_offset: Vec2::ZERO,
+        cam_zoom: 1.0,
+        pan_from: None,
+        compass: Compass {
+            corner: options.compass,
+            color: rgb(1.0, 1.0, 1.0),
+        },
+        show_compass: true,
+        noclip: false,
+        paused: false,
+        cursor_grab: true,
+        time: 0.0,
+        edit_start: None,
+        snap_to_grid: false,
+        snap_size: 25.0,
+        unsaved_edits: false,
+        maze_seed: 0,
+        footsteps: 0,
+        step_loudness: 0.0,
+        hidden_layers: HashSet::new(),
+        spawns,
+        gamepad,
+        record: None,
+        map_watch: (!is_image).then(|| MapWatch::new(map_path)),
+        map_error: None,
+        playback: None,
+    };
+
+    apply_cursor_grab(app, &model);
+
+    model
+}
+
+// Only the branches that can change what's drawn mark the view dirty; held movement
+// keys show up as a camera change in `update` instead.
+fn event(app: &App, model: &mut Model, event: Event) {
+    match event {
+        Event::DeviceEvent(_, data) => match data {
+            DeviceEvent::Key(key) => {
+                model.moves.update_moves(key, &model.bindings);
+                model.moves.update_view_moves(key);
+                for split in &mut model.split_players {
+                    split.moves.update_moves(key, &split.bindings);
+                }
+
+                if let Some(pressed) = model.key_edges.just_pressed(key) {
+                    model.dirty = true;
+                    model.update_toggles(pressed);
+                    apply_cursor_grab(app, model);
+
+                    match pressed {
+                        Key::F2 => model.toggle_split_screen(app.window_rect()),
+                        Key::P => take_screenshot(app),
+                        Key::Q => quit(app, model),
+                        // The window then reports a resize, which refits the edge walls.
+                        Key::F11 => {
+                            let window = app.main_window();
+                            window.set_fullscreen(!window.is_fullscreen());
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            DeviceEvent::MouseMotion { delta }
+                if !model.paused && model.cursor_grab && delta != (0.0, 0.0) =>
+            {
+                model.dirty = true;
+                model.moves.mouse_look +=
+                    vec2(delta.0 as f32, -delta.1 as f32) * model.mouse_sensitivity;
+            }
+            _ => {}
+        },
+        // Some platforms drop the grab when the window loses focus without saying so.
+        Event::WindowEvent {
+            simple: Some(WindowEvent::Focused),
+            ..
+        } => apply_cursor_grab(app, model),
+        Event::WindowEvent {
+            simple: Some(WindowEvent::Resized(size)),
+            ..
+        } => {
+            model.dirty = true;
+            model.resize(Rect::from_wh(size));
+        }
+        Event::WindowEvent {
+            simple: Some(WindowEvent::MousePressed(MouseButton::Left)),
+            ..
+        } if model.view_mode == ViewMode::RayDebug => {
+            model.dirty = true;
+            model.place_boundary_point(model.snapped(model.debug_to_world(app.mouse.position())))
+        }
+        Event::WindowEvent {
+            simple: Some(WindowEvent::MousePressed(MouseButton::Right)),
+            ..
+        } if model.view_mode == ViewMode::RayDebug => {
+            let target = model.debug_to_world(app.mouse.position());
+            model.dirty = true;
+            model.act(RecordedAction::LookAt(target));
+        }
+        Event::WindowEvent {
+            simple: Some(WindowEvent::MousePressed(MouseButton::Middle)),
+            ..
+        } if model.view_mode == ViewMode::RayDebug => model.pan_from = Some(app.mouse.position()),
+        Event::WindowEvent {
+            simple: Some(WindowEvent::MouseReleased(MouseButton::Middle)),
+            ..
+        } => model.pan_from = None,
+        Event::WindowEvent {
+            simple: Some(WindowEvent::MouseMoved(position)),
+            ..
+        } => {
+            if let Some(from) = model.pan_from.replace(position) {
+                model.dirty = true;
+                model.cam_offset += position - from;
+            }
+        }
+        Event::WindowEvent {
+            simple: Some(WindowEvent::MouseWheel(delta, _)),
+            ..
+        } if model.view_mode == ViewMode::RayDebug => {
+            let steps = match delta {
+                MouseScrollDelta::LineDelta(_, y) => y,
+                MouseScrollDelta::PixelDelta(pos) => pos.y as f32 / PIXELS_PER_SCROLL_LINE,
+            };
+            model.dirty = true;
+            model.zoom_debug(app.mouse.position(), steps);
+        }
+        _ => {}
+    }
+}
+
+// Editor changes that were never saved are written to a temp file first, so a
+// stray Q doesn't lose them.
+fn quit(app: &App, model: &Model) {
+    if model.unsaved_edits {
+        if let Err(err) = autosave(model) {
+            eprintln!("Failed to save unsaved edits: {}", err);
+        }
+    }
+
+    app.quit();
+}
+
+fn autosave(model: &Model) -> io::Result<()> {
+    let path = env::temp_dir().join(AUTOSAVE_FILE);
+    save_scene(model, &path.to_string_lossy())?;
+    println!("Saved unsaved edits to {}", path.display());
+
+    Ok(())
+}
+
+fn apply_cursor_grab(app: &App, model: &Model) {
+    let window = app.main_window();
+    let grab = model.cursor_grab && model.view_mode != ViewMode::RayDebug && !model.paused;
+
+    if let Err(err) = window.set_cursor_grab(grab) {
+        eprintln!("Failed to grab cursor: {}", err);
+    }
+    window.set_cursor_visible(!grab);
+}
+
+fn update(app: &App, model: &mut Model, update: Update) {
+    let dt = update.since_last.as_secs_f32();
+
+    model.record_frame_time(dt);
+    model.poll_map(dt);
+
+    if !model.paused {
+        let before = model.camera_state();
+        model.advance(dt);
+        if model.camera_state() != before {
+            model.dirty = true;
+        }
+    }
+
+    // Split screen gives each player an equal slice of the width, and so of the
+    // columns.
+    let width = app.window_rect().w() / (model.split_players.len() + 1) as f32 * model.render_scale;
+    let offset = |player: &Player| {
+        if model.stable_columns {
+            ColumnAngles::stable_offset(model.fov, model.resolution, width, player.look_dir.angle())
+        } else {
+            0.0
+        }
+    };
+    let first = offset(&model.player);
+    let split_offsets: Vec<f32> = model
+        .split_players
+        .iter()
+        .map(|split| offset(&split.player))
+        .collect();
+
+    model
+        .column_angles
+        .update(model.fov, model.resolution, width, first);
+    for (split, offset) in model.split_players.iter_mut().zip(split_offsets) {
+        split
+            .angles
+            .update(model.fov, model.resolution, width, offset);
+    }
+
+    if model.dirty {
+        model.recast();
+    }
+}
+
+fn view(app: &App, model: &Model, frame: Frame) {
+    let draw = app.draw();
+    let boundaries = app.window_rect();
+
+    draw.background().color(PLUM);
+
+    if model.view_mode == ViewMode::RayDebug {
+        draw_ray_debug(app, &draw, model);
+    } else {
+        let areas = split_screen(boundaries, model.split_players.len() + 1);
+
+        draw_camera(
+            &draw,
+            model,
+            &model.player,
+            &model.column_angles,
+            &model.columns,
+            areas[0],
+        );
+        for (split, &area) in model.split_players.iter().zip(&areas[1..]) {
+            draw_camera(
+                &draw,
+                model,
+                &split.player,
+                &split.angles,
+                &split.columns,
+                area,
+            );
+            draw.line()
+                .start(area.bottom_left())
+                .end(area.top_left())
+                .weight(2.0)
+                .color(BLACK);
+        }
+    }
+
+    if let Some(err) = &model.map_error {
+        draw.text(&format!("Map error: {}", err))
+            .x_y(0.0, boundaries.bottom() + 20.0)
+            .w(boundaries.w() - 20.0)
+            .color(RED);
+    }
+
+    if model.noclip {
+        draw.text("NOCLIP")
+            .x_y(0.0, boundaries.top() - 20.0)
+            .color(YELLOW);
+    }
+
+    if !model.hidden_layers.is_empty() {
+        let mut layers: Vec<u32> = model.hidden_layers.iter().copied().collect();
+        layers.sort_unstable();
+        let layers: Vec<String> = layers.iter().map(u32::to_string).collect();
+
+        draw.text(&format!("Hidden layers: {}", layers.join(", ")))
+            .x_y(0.0, boundaries.top() - 40.0)
+            .w(boundaries.w() - 20.0)
+            .color(YELLOW);
+    }
+
+    if model.show_debug {
+        draw.text(&format!("{}px columns", model.resolution))
+            .x_y(boundaries.right() - 60.0, boundaries.top() - 20.0)
+            .color(WHITE);
+
+        if model.ray_count > 0 {
+            draw.text(&format!("{} rays", model.ray_count))
+                .x_y(boundaries.right() - 60.0, boundaries.top() - 40.0)
+                .color(WHITE);
+        }
+    }
+
+    if model.paused {
+        draw.text("PAUSED")
+            .xy(boundaries.xy())
+            .font_size(48)
+            .w(boundaries.w())
+            .color(WHITE);
+    }
+
+    if model.show_fps {
+        draw.text(&format!("{:.0} FPS", model.fps()))
+            .x_y(boundaries.left() + 50.0, boundaries.top() - 20.0)
+            .color(WHITE);
+    }
+
+    if model.show_debug {
+        let player = &model.player;
+        let text = format!(
+            "pos {:.1}, {:.1}\nangle {:.1}°\nfov {:.0}°\nres {}px\nwalls {}\nsteps {} ({:.2})",
+            player.pos.x,
+            player.pos.y,
+            player.look_dir.angle().to_degrees(),
+            model.fov,
+            model.resolution,
+            model.boundaries.len(),
+            model.footsteps,
+            model.step_loudness,
+        );
+
+        // Below the FPS counter, which shares the top left corner.
+        let rect = Rect::from_w_h(200.0, 120.0).top_left_of(boundaries.pad(10.0).pad_top(30.0));
+
+        draw.text(&text)
+            .xy(rect.xy())
+            .wh(rect.wh())
+            .left_justify()
+            .align_text_top()
+            .color(WHITE);
+    }
+
+    draw.to_frame(app, &frame).unwrap();
+}
+
+fn next_render_scale(scale: f32) -> f32 {
+    let current = RENDER_SCALES.iter().position(|&preset| preset == scale);
+
+    RENDER_SCALES[current.map_or(0, |i| (i + 1) % RENDER_SCALES.len())]
+}
+
+/// Halves or doubles `ray_count` for a view `columns` wide. Steps that reach or
+/// cross the column count stop at one ray per column (0) first, so it can always
+/// get back there.
+fn next_ray_count(ray_count: i32, columns: i32, up: bool) -> i32 {
+    let current = match ray_count {
+        0 => columns,
+        count => count,
+    };
+    let next = if up { current * 2 } else { current / 2 }.clamp(MIN_RAY_COUNT, MAX_RAY_COUNT);
+    let crosses = ray_count != 0 && (current < columns) != (next <= columns);
+
+    if crosses || next == columns {
+        0
+    } else {
+        next
+    }
+}
+
+// Equal side by side slices of the window, one per player, left to right.
+fn split_screen(window: Rect, players: usize) -> Vec<Rect> {
+    let width = window.w() / players as f32;
+
+    (0..players)
+        .map(|i| {
+            let left = window.left() + i as f32 * width;
+            Rect::from_corners(pt2(left, window.bottom()), pt2(left + width, window.top()))
+        })
+        .collect()
+}
+
+/// Draws what `player` sees, in the first-person or top-down view, into `area` of
+/// the window. `angles` must be built for the width of `area`, and `columns` cast
+/// with them.
+fn draw_camera(
+    window_draw: &Draw,
+    model: &Model,
+    player: &Player,
+    angles: &ColumnAngles,
+    columns: &[ColumnResult],
+    area: Rect,
+) {
+    // Everything but the minimap is drawn relative to the centre of `area`. The
+    // scene itself is drawn `render_scale` times smaller and scaled back up, while
+    // the overlays on top stay at window size.
+    let overlay = window_draw.scissor(area).translate(area.xy().extend(0.0));
+    let window = Rect::from_wh(area.wh());
+    let draw = overlay.scale(1.0 / model.render_scale);
+    let boundaries = Rect::from_wh(area.wh() * model.render_scale);
+
+    let horizon = player.horizon(boundaries);
+    let projection = projection_distance(model.fov, boundaries.w());
+
+    if model.view_mode == ViewMode::FirstPerson {
+        let floor_band = (horizon - boundaries.bottom()) / FLOOR_BANDS as f32;
+        let ceiling_band = (boundaries.top() - horizon) / FLOOR_BANDS as f32;
+
+        for band in 0..FLOOR_BANDS {
+            let floor_y = boundaries.bottom() + (band as f32 + 0.5) * floor_band;
+            let ceiling_y = boundaries.top() - (band as f32 + 0.5) * ceiling_band;
+            let shade = map_range(floor_y, boundaries.bottom(), horizon, 1.0, 0.3);
+
+            // A wall reaches half its projected height above and below the horizon,
+            // so a floor or ceiling band at offset y sits where a wall's half-height
+            // would project to y.
+            draw.rect()
+                .x_y(0.0, floor_y)
+                .w_h(boundaries.w(), floor_band)
+                .color(model.fog.apply(
+                    rgb(
+                        model.floor_color.red * shade,
+                        model.floor_color.green * shade,
+                        model.floor_color.blue * shade,
+                    ),
+                    WALL_HEIGHT / 2.0 * projection / (horizon - floor_y),
+                ));
+
+            draw.rect()
+                .x_y(0.0, ceiling_y)
+                .w_h(boundaries.w(), ceiling_band)
+                .color(model.fog.apply(
+                    model.sky.at(1.0 - (band as f32 + 0.5) / FLOOR_BANDS as f32),
+                    WALL_HEIGHT / 2.0 * projection / (ceiling_y - horizon),
+                ));
+        }
+    }
+
+    // A full-height wall is centred on the horizon. Other heights keep the same base
+    // on the floor and grow or shrink from there. Depths are clamped to the near clip
+    // so a wall right in front of the eye doesn't blow up to an enormous height.
+    let column_height = |depth: f32, wall_height: f32| {
+        projected_wall_height(depth, projection, model.near_clip) * wall_height
+    };
+    let column_centre = |depth: f32, height: f32| {
+        horizon + (height - projected_wall_height(depth, projection, model.near_clip)) / 2.0
+    };
+
+    // The lit surface colour: ambient plus diffuse light on the albedo, then a
+    // white highlight where the reflected view direction points back at the
+    // player's lamp.
+    let light_hit = |luminosity: f32,
+                     point: Point2,
+                     normal: Option<Vec2>,
+                     dir: Vec2,
+                     albedo: Rgb,
+                     material: Material| {
+        let light = shade(
+            luminosity + model.ambient + scene_light(&model.lights, point, normal),
+            model.shading_gamma,
+        );
+
+        let (diffuse, highlight) = match normal {
+            Some(normal) => {
+                let facing = normal.dot(-dir).max(0.0);
+                let reflected = dir - 2.0 * dir.dot(normal) * normal;
+                (
+                    light * (1.0 - model.normal_shading + model.normal_shading * facing),
+                    light * reflected.dot(-dir).max(0.0).powi(SHININESS),
+                )
+            }
+            None => (light, 0.0),
+        };
+
+        let brightness = material.ambient + diffuse;
+        let specular = material.specular * highlight;
+
+        rgb(
+            brightness * albedo.red * material.albedo.red + specular,
+            brightness * albedo.green * material.albedo.green + specular,
+            brightness * albedo.blue * material.albedo.blue + specular,
+        )
+    };
+
+    let depth_buffer: Vec<f32> = columns.iter().map(|column| column.depth).collect();
+    let mut walls = ColumnMesh::default();
+    // The centre and height of each drawn column, for the outline pass.
+    let mut spans: Vec<(f32, f32)> = Vec::with_capacity(depth_buffer.len());
+    // Each column's glass panes back to front, for tinting sprites behind them.
+    let mut pane_buffer: Vec<Vec<PaneSpan>> = Vec::with_capacity(depth_buffer.len());
+
+    for &ColumnResult {
+        column: i,
+        length,
+        luminosity: hit_luminosity,
+        depth,
+        ref ray,
+    } in columns
+    {
+        let x = angles.left_edge(i) + model.resolution as f32 / 2.0;
+
+        // A miss is drawn as a wall at the far edge of the fog, which shades fully
+        // to the fog colour, so openings read as distance rather than a gap.
+        let (depth, height) = match length {
+            Some(length) => {
+                let depth = if model.fisheye_correction {
+                    depth
+                } else {
+                    length
+                };
+                (depth, column_height(depth, ray.height))
+            }
+            None => (model.fog.end, column_height(model.fog.end, 1.0)),
+        };
+        let centre = column_centre(depth, height);
+        spans.push((centre, height));
+
+        let lit = match (hit_luminosity, ray.end) {
+            (Some(lum), Some(end)) => {
+                light_hit(lum, end, ray.normal, ray.dir, ray.color, ray.material)
+            }
+            _ => rgb(0.0, 0.0, 0.0),
+        };
+        let lit = match ray.side {
+            Some(WallSide::NorthSouth) => rgb(
+                lit.red * model.side_shading,
+                lit.green * model.side_shading,
+                lit.blue * model.side_shading,
+            ),
+            _ => lit,
+        };
+
+        let distance = length.unwrap_or(f32::INFINITY);
+
+        match (&ray.texture, ray.offset) {
+            (Some(texture), Some(offset)) => {
+                let tex_w = texture.width();
+                let u = ((offset * tex_w as f32) as u32).min(tex_w.saturating_sub(1));
+
+                draw_texture_column(
+                    &mut walls,
+                    texture,
+                    u,
+                    Rect::from_x_y_w_h(x, centre, model.resolution as f32, height),
+                    |texel, _| {
+                        model.fog.apply(
+                            rgb(
+                                lit.red * texel.red,
+                                lit.green * texel.green,
+                                lit.blue * texel.blue,
+                            ),
+                            distance,
+                        )
+                    },
+                    1.0,
+                );
+            }
+            _ => {
+                let color = model.fog.apply(lit, distance);
+
+                walls.push_rect(
+                    Rect::from_x_y_w_h(x, centre, model.resolution as f32, height),
+                    rgba(color.red, color.green, color.blue, 1.0),
+                );
+            }
+        }
+
+        // Alpha blending back to front composites the panes over the wall behind.
+        let mut panes = Vec::with_capacity(ray.glass.len());
+        for pane in ray.glass.iter().rev() {
+            let depth = if model.fisheye_correction {
+                pane.distance * ray.dir.dot(player.look_dir)
+            } else {
+                pane.distance
+            };
+            let height = column_height(depth, pane.height);
+            let lit = light_hit(
+                luminosity(pane.distance),
+                pane.point,
+                Some(pane.normal),
+                pane.dir,
+                pane.color,
+                pane.material,
+            );
+            let color = model.fog.apply(lit, pane.distance);
+            let rect = Rect::from_x_y_w_h(
+                x,
+                column_centre(depth, height),
+                model.resolution as f32,
+                height,
+            );
+            let color = rgba(color.red, color.green, color.blue, pane.opacity);
+
+            walls.push_rect(rect, color);
+            panes.push(PaneSpan {
+                depth: pane.distance * ray.dir.dot(player.look_dir),
+                rect,
+                color,
+            });
+        }
+        pane_buffer.push(panes);
+    }
+
+    if model.show_outlines && model.view_mode == ViewMode::FirstPerson {
+        let first_column = angles.first_column();
+        let color = model.outlines.color;
+
+        for (i, pair) in depth_buffer.windows(2).enumerate() {
+            if !is_outline_edge(pair[0], pair[1], model.outlines.threshold) {
+                continue;
+            }
+
+            // The nearer column is the one whose silhouette the edge belongs to.
+            let (centre, height) = if pair[0] < pair[1] {
+                spans[i]
+            } else {
+                spans[i + 1]
+            };
+            let x = angles.left_edge(first_column + i as i32 + 1);
+
+            walls.push_rect(
+                Rect::from_x_y_w_h(x, centre, 2.0, height),
+                rgba(color.red, color.green, color.blue, 1.0),
+            );
+        }
+    }
+
+    walls.draw(&draw);
+
+    if model.show_wireframe && model.view_mode == ViewMode::FirstPerson && !model.use_grid {
+        draw_wireframe(&draw, model, player, horizon, projection);
+    }
+
+    if model.view_mode == ViewMode::TopDown {
+        // Drawing through a transform keeps the player at the centre of the view with
+        // `look_dir` pointing up, however the level is laid out.
+        let rotation = PI / 2.0 - player.look_dir.angle();
+        let relative = overlay.rotate(rotation).translate(-player.pos.extend(0.0));
+
+        draw_level(&relative, model);
+
+        let mut fog = ColumnMesh::default();
+        for cell in model.explored.hidden_cells() {
+            fog.push_rect(cell, rgba(0.0, 0.0, 0.0, 1.0));
+        }
+        fog.draw(&relative);
+    } else {
+        draw_sprites(
+            &draw,
+            model,
+            player,
+            angles,
+            boundaries,
+            &depth_buffer,
+            &pane_buffer,
+        );
+
+        if let Some(viewmodel) = &model.viewmodel {
+            draw_viewmodel(&draw, viewmodel, player, boundaries);
+        }
+    }
+
+    if model.show_minimap && model.view_mode == ViewMode::FirstPerson {
+        model
+            .minimap
+            .anchored(area)
+            .draw(window_draw, model.world(), &model.explored, player);
+    }
+
+    if model.show_crosshair && model.view_mode == ViewMode::FirstPerson {
+        model.crosshair.draw(&overlay, window);
+    }
+
+    if model.show_compass {
+        model.compass.draw(&overlay, window, player.look_dir);
+    }
+}
+
+// The level from above, with each ray the player casts, through the debug camera.
+fn draw_ray_debug(app: &App, draw: &Draw, model: &Model) {
+    let debug = draw
+        .translate(model.cam_offset.extend(0.0))
+        .scale(model.cam_zoom);
+
+    for column in &model.columns {
+        column
+            .ray
+            .show(&debug, model.line_weights.ray, model.ambient);
+    }
+
+    if model.snap_to_grid {
+        draw_snap_grid(&debug, model, app.window_rect());
+    }
+
+    draw_level(&debug, model);
+
+    if let Some(start) = model.edit_start {
+        debug
+            .line()
+            .start(start)
+            .end(model.snapped(model.debug_to_world(app.mouse.position())))
+            .weight(2.0)
+            .color(DARKGREEN);
+    }
+}
+
+// Faint lines at every snap increment across the part of the world in view.
+fn draw_snap_grid(draw: &Draw, model: &Model, window: Rect) {
+    let size = model.snap_size;
+    let bottom_left = model.debug_to_world(window.bottom_left());
+    let top_right = model.debug_to_world(window.top_right());
+    let color = rgba(1.0, 1.0, 1.0, 0.15);
+    // One pixel wide whatever the zoom.
+    let weight = 1.0 / model.cam_zoom;
+
+    // Zoomed far out the lines would merge into a solid wash, so leave them out.
+    if size * model.cam_zoom < 4.0 {
+        return;
+    }
+
+    let mut x = (bottom_left.x / size).ceil() * size;
+    while x <= top_right.x {
+        draw.line()
+            .start(pt2(x, bottom_left.y))
+            .end(pt2(x, top_right.y))
+            .weight(weight)
+            .color(color);
+        x += size;
+    }
+
+    let mut y = (bottom_left.y / size).ceil() * size;
+    while y <= top_right.y {
+        draw.line()
+            .start(pt2(bottom_left.x, y))
+            .end(pt2(top_right.x, y))
+            .weight(weight)
+            .color(color);
+        y += size;
+    }
+}
+
+fn cast_view(model: &Model, player: &Player, angles: &ColumnAngles) -> Vec<ColumnResult> {
+    match model.ray_count {
+        0 => cast_angles(model, player, angles, model.aa_samples),
+        count => {
+            let rays = cast_angles(model, player, &angles.rays(count), 1);
+            interpolate_rays(angles, &rays)
+        }
+    }
+}
+
+fn cast_angles(
+    model: &Model,
+    player: &Player,
+    angles: &ColumnAngles,
+    aa_samples: u32,
+) -> Vec<ColumnResult> {
+    if model.use_grid {
+        cast_grid_scene(player, &model.grid, angles, model.max_distance, aa_samples)
+    } else {
+        cast_scene(
+            player,
+            model.world(),
+            angles,
+            model.max_bounces,
+            model.max_distance,
+            aa_samples,
+        )
+    }
+}
+
+/// Column and texel rectangles collected over a frame and submitted as one mesh,
+/// rather than one `draw.rect()` per rectangle.
+#[derive(Default)]
+struct ColumnMesh {
+    vertices: Vec<(Point3, Rgba)>,
+}
+
+impl ColumnMesh {
+    fn push_rect(&mut self, rect: Rect, color: Rgba) {
+        let (bottom_left, bottom_right, top_right, top_left) = (
+            pt3(rect.left(), rect.bottom(), 0.0),
+            pt3(rect.right(), rect.bottom(), 0.0),
+            pt3(rect.right(), rect.top(), 0.0),
+            pt3(rect.left(), rect.top(), 0.0),
+        );
+
+        for corner in [
+            bottom_left,
+            bottom_right,
+            top_right,
+            bottom_left,
+            top_right,
+            top_left,
+        ] {
+            self.vertices.push((corner, color));
+        }
+    }
+
+    fn draw(self, draw: &Draw) {
+        if !self.vertices.is_empty() {
+            draw.mesh().points_colored(self.vertices);
+        }
+    }
+}
+
+// The level from above in world coordinates, with the player on top.
+fn draw_level(draw: &Draw, model: &Model) {
+    if model.use_grid {
+        model.grid.show(draw);
+    } else {
+        let weight = model.line_weights.boundary;
+
+        let shown = model
+            .boundaries
+            .iter()
+            .filter(|boundary| model.world().shows(boundary));
+        for boundary in shown {
+            boundary.show(draw, weight);
+        }
+        for circle in &model.circles {
+            circle.show(draw, weight);
+        }
+        for door in &model.doors {
+            door.show(draw, weight);
+        }
+        for portal in &model.portals {
+            portal.show(draw, weight);
+        }
+    }
+
+    model.player.show_player(draw, model.line_weights.player);
+    for split in &model.split_players {
+        split.player.show_player(draw, model.line_weights.player);
+    }
+}
+
+fn draw_sprites(
+    draw: &Draw,
+    model: &Model,
+    player: &Player,
+    angles: &ColumnAngles,
+    window: Rect,
+    depth_buffer: &[f32],
+    pane_buffer: &[Vec<PaneSpan>],
+) {
+    let first_column = angles.first_column();
+    let horizon = player.horizon(window);
+    let projection = projection_distance(model.fov, window.w());
+    let mut mesh = ColumnMesh::default();
+
+    for sprite in sprites_back_to_front(&model.sprites, player.pos) {
+        let relative = sprite.pos - player.pos;
+        let depth = relative.dot(player.look_dir);
+
+        if depth <= 0.0 {
+            continue;
+        }
+
+        // Same projection the wall rays use: screen x is proportional to the tangent
+        // of the angle off the look direction. Like the walls, a sprite is never
+        // drawn nearer than the near clip, so one on top of the player stays finite.
+        let lateral = relative.dot(player.look_dir.perp());
+        let screen_x = lateral / depth.max(model.near_clip) * projection;
+
+        let texture = sprite.texture_from(player.pos);
+        let (tex_w, tex_h) = texture.dimensions();
+        let height = projected_wall_height(depth, projection, model.near_clip);
+        let width = height * tex_w as f32 / tex_h as f32;
+        let left = screen_x - width / 2.0;
+
+        let light = shade(
+            luminosity(depth) + model.ambient + scene_light(&model.lights, sprite.pos, None),
+            model.shading_gamma,
+        );
+
+        let start_column = angles.column_at(left);
+        let end_column = ((left + width) / model.resolution as f32 - angles.offset).ceil() as i32;
+
+        for column in start_column..end_column {
+            let index = match usize::try_from(column - first_column) {
+                Ok(index) if depth_buffer.get(index).is_some_and(|&wall| depth < wall) => index,
+                _ => continue,
+            };
+            let in_front: Vec<&PaneSpan> = pane_buffer
+                .get(index)
+                .into_iter()
+                .flatten()
+                .filter(|pane| pane.depth < depth)
+                .collect();
+
+            let x = angles.left_edge(column) + model.resolution as f32 / 2.0;
+            let u =
+                (((x - left) / width * tex_w as f32).max(0.0) as u32).min(tex_w.saturating_sub(1));
+
+            draw_texture_column(
+                &mut mesh,
+                texture,
+                u,
+                Rect::from_x_y_w_h(x, horizon, model.resolution as f32, height),
+                |texel, y| {
+                    let lit = model.fog.apply(
+                        rgb(light * texel.red, light * texel.green, light * texel.blue),
+                        depth,
+                    );
+                    tint_through(lit, &in_front, y)
+                },
+                1.0,
+            );
+        }
+    }
+
+    mesh.draw(draw);
+}
+
+// Projects each wall's ends the way the columns are, so a wall's outline should
+// hug its columns wherever it isn't hidden. Doors are outlined as far as they're
+// still closed. Walls crossing the near clip are cut there; walls entirely behind
+// it are skipped. Without fisheye correction the height follows the distance
+// rather than the depth, so the top and bottom edges are bent to match.
+fn draw_wireframe(draw: &Draw, model: &Model, player: &Player, horizon: f32, projection: f32) {
+    let to_view = |point: Point2| {
+        let relative = point - player.pos;
+        vec2(
+            relative.dot(player.look_dir.perp()),
+            relative.dot(player.look_dir),
+        )
+    };
+
+    let walls = model
+        .boundaries
+        .iter()
+        .filter(|boundary| model.world().shows(boundary))
+        .map(|boundary| (boundary, boundary.length));
+    let doors = model
+        .doors
+        .iter()
+        .filter(|door| door.length() > 0.0)
+        .map(|door| (&door.boundary, door.length()));
+
+    for (boundary, length) in walls.chain(doors) {
+        let mut a = to_view(boundary.origin);
+        let mut b = to_view(boundary.origin + length * boundary.dir);
+
+        if a.y < model.near_clip && b.y < model.near_clip {
+            continue;
+        }
+        if a.y < model.near_clip {
+            a = a.lerp(b, (model.near_clip - a.y) / (b.y - a.y));
+        } else if b.y < model.near_clip {
+            b = b.lerp(a, (model.near_clip - b.y) / (a.y - b.y));
+        }
+
+        // Same base on the floor and height as `column_height` and `column_centre`.
+        let corners = |end: Vec2| {
+            let x = end.x / end.y * projection;
+            let distance = if model.fisheye_correction {
+                end.y
+            } else {
+                end.length()
+            };
+            let full = projected_wall_height(distance, projection, model.near_clip);
+            let bottom = horizon - full / 2.0;
+            (pt2(x, bottom), pt2(x, bottom + full * boundary.height))
+        };
+        let segments = if model.fisheye_correction {
+            1
+        } else {
+            WIREFRAME_SEGMENTS
+        };
+        let (bottoms, tops): (Vec<Point2>, Vec<Point2>) = (0..=segments)
+            .map(|step| corners(a.lerp(b, step as f32 / segments as f32)))
+            .unzip();
+
+        draw.polyline()
+            .weight(1.0)
+            .points_closed(bottoms.into_iter().chain(tops.into_iter().rev()))
+            .color(LIME);
+    }
+}
+
+// Bottom centre of the view, swaying with the walk: side to side once per bob
+// cycle and dipping at each end of the swing.
+fn draw_viewmodel(draw: &Draw, texture: &wgpu::Texture, player: &Player, window: Rect) {
+    let [tex_w, tex_h] = texture.size();
+    let height = window.h() * VIEWMODEL_SCALE;
+    let width = height * tex_w as f32 / tex_h as f32;
+    let swing = player.bob_phase.sin();
+    let sway = player.bob_amount * vec2(VIEWMODEL_SWAY * swing, -BOB_HEIGHT * swing.abs());
+    let rect = Rect::from_w_h(width, height)
+        .mid_bottom_of(window)
+        .shift(sway);
+
+    draw.texture(texture).xy(rect.xy()).wh(rect.wh());
+}
+
+/// A glass pane as drawn over one column: how far in front of the camera it is,
+/// where it covers, and its colour with its opacity as alpha.
+struct PaneSpan {
+    depth: f32,
+    rect: Rect,
+    color: Rgba,
+}
+
+/// Blends `color` at screen height `y` through `panes`, back to front, the same way
+/// drawing them over it would.
+fn tint_through(color: Rgb, panes: &[&PaneSpan], y: f32) -> Rgb {
+    panes
+        .iter()
+        .filter(|pane| (pane.rect.bottom()..=pane.rect.top()).contains(&y))
+        .fold(color, |color, pane| {
+            let alpha = pane.color.alpha;
+            rgb(
+                color.red + (pane.color.red - color.red) * alpha,
+                color.green + (pane.color.green - color.green) * alpha,
+                color.blue + (pane.color.blue - color.blue) * alpha,
+            )
+        })
+}
+
+// The mesh draws in push order, so the farthest sprite goes first and nearer ones
+// cover it where they overlap.
+fn sprites_back_to_front(sprites: &[Sprite], eye: Point2) -> Vec<&Sprite> {
+    let mut sorted: Vec<&Sprite> = sprites.iter().collect();
+
+    sorted.sort_by(|a, b| {
+        b.pos
+            .distance_squared(eye)
+            .total_cmp(&a.pos.distance_squared(eye))
+    });
+
+    sorted
+}
+
+fn draw_texture_column(
+    mesh: &mut ColumnMesh,
+    texture: &RgbaImage,
+    u: u32,
+    column: Rect,
+    shade: impl Fn(Rgb, f32) -> Rgb,
+    alpha: f32,
+) {
+    let tex_h = texture.height();
+    let texel_height = column.h() / tex_h as f32;
+
+    for v in 0..tex_h {
+        let pixel = texture.get_pixel(u, v);
+
+        if pixel[3] == 0 {
+            continue;
+        }
+
+        let y = column.top() - (v as f32 + 0.5) * texel_height;
+        let color = shade(
+            rgb(
+                pixel[0] as f32 / 255.0,
+                pixel[1] as f32 / 255.0,
+                pixel[2] as f32 / 255.0,
+            ),
+            y,
+        );
+
+        mesh.push_rect(
+            Rect::from_x_y_w_h(column.x(), y, column.w(), texel_height),
+            rgba(
+                color.red,
+                color.green,
+                color.blue,
+                alpha * pixel[3] as f32 / 255.0,
+            ),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn assert_close(actual: f32, expected: f32) {
+        assert!(
+            (actual - expected).abs() < 1e-3,
+            "expected {}, got {}",
+            expected,
+            actual
+        );
+    }
+
+    // Runs `f` with a world holding just `boundaries`, nothing hidden.
+    fn with_world<T>(boundaries: &[Boundary], f: impl FnOnce(World) -> T) -> T {
+        let index = BoundaryIndex::build(boundaries);
+        let hidden_layers = HashSet::new();
+
+        f(World {
+            boundaries,
+            hidden_layers: &hidden_layers,
+            circles: &[],
+            doors: &[],
+            portals: &[],
+            index: &index,
+        })
+    }
+
+    // Writes `lines` as `map.txt` in a fresh temp directory, next to each texture
+    // file given as a name and its bytes. `{dir}` in a line becomes that directory,
+    // so lines can point at the textures. Remove `map.parent()` when done.
+    fn temp_map(name: &str, lines: &[&str], textures: &[(&str, &[u8])]) -> PathBuf {
+        let dir = env::temp_dir().join(format!("raycaster-{}-{}", name, process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        for (file, bytes) in textures {
+            fs::write(dir.join(file), bytes).unwrap();
+        }
+
+        let map = dir.join("map.txt");
+        let contents = lines.join("\n").replace("{dir}", &dir.to_string_lossy()) + "\n";
+        fs::write(&map, contents).unwrap();
+
+        map
+    }
+
+    // A 2x2 black PPM, which the image crate reads like any other texture.
+    const SMALL_TEXTURE: &[u8] = b"P6\n2 2\n255\n\0\0\0\0\0\0\0\0\0\0\0\0";
+
+    fn player_at(pos: Point2, look_dir: Vec2) -> Player {
+        Player {
+            pos,
+            look_dir,
+            target_look_dir: look_dir,
+            ..Player::new()
+        }
+    }
+
+    fn sprite_at(pos: Point2) -> Sprite {
+        Sprite {
+            pos,
+            facing: vec2(1.0, 0.0),
+            textures: vec![RgbaImage::new(1, 1)],
+        }
+    }
+
+    #[test]
+    fn overlapping_sprites_draw_farthest_first() {
+        // Both sit straight ahead of the eye, so the nearer one covers the other.
+        let sprites = [
+            sprite_at(pt2(50.0, 0.0)),
+            sprite_at(pt2(200.0, 0.0)),
+            sprite_at(pt2(100.0, 0.0)),
+        ];
+
+        let order: Vec<f32> = sprites_back_to_front(&sprites, pt2(0.0, 0.0))
+            .iter()
+            .map(|sprite| sprite.pos.x)
+            .collect();
+
+        assert_eq!(order, vec![200.0, 100.0, 50.0]);
+    }
+
+    #[test]
+    fn long_wall_straight_ahead_projects_flat_and_symmetric() {
+        let walls = [Boundary::new(pt2(200.0, -2000.0), pt2(200.0, 2000.0))];
+        let player = player_at(pt2(0.0, 0.0), vec2(1.0, 0.0));
+
+        with_world(&walls, |world| {
+            for fov in [MIN_FOV, 60.0, MAX_FOV] {
+                let angles = ColumnAngles::new(fov, 5, 800.0);
+                let columns = cast_scene(&player, world, &angles, 0, MAX_DISTANCE, 1);
+                let depths: Vec<f32> = columns.iter().map(|column| column.depth).collect();
+
+                for &depth in &depths {
+                    assert_close(depth, 200.0);
+                }
+                for (left, right) in depths.iter().zip(depths.iter().rev()) {
+                    assert_close(*left, *right);
+                }
+            }
+        });
+    }
+
+    #[test]
+    fn fov_keys_clamp_to_the_allowed_range() {
+        let mut moves = Moves::new();
+
+        moves.widen_fov = true;
+        assert_eq!(moves.update_fov(MAX_FOV - 1.0, 1.0), MAX_FOV);
+
+        moves.widen_fov = false;
+        moves.narrow_fov = true;
+        assert_eq!(moves.update_fov(MIN_FOV + 1.0, 1.0), MIN_FOV);
+    }
+
+    #[test]
+    fn column_angles_stay_symmetric_at_every_fov() {
+        let mut fov = MIN_FOV;
+
+        while fov <= MAX_FOV {
+            for (resolution, width) in [(1, 801.0), (5, 800.0), (7, 1024.0)] {
+                let angles = ColumnAngles::new(fov, resolution, width);
+                let (first, last) = (angles.columns[0], *angles.columns.last().unwrap());
+
+                assert_eq!(first.0, -last.0 - 1);
+                for (&(_, left), &(_, right)) in
+                    angles.columns.iter().zip(angles.columns.iter().rev())
+                {
+                    assert_close(left, -right);
+                }
+                assert!(last.1 < (fov / 2.0).to_radians() + 0.05);
+            }
+            fov += 7.5;
+        }
+    }
+
+    #[test]
+    fn one_second_of_input_moves_the_same_at_any_frame_rate() {
+        // Instant acceleration and turning leave just the per-second rates, which
+        // must add up to the same distance and angle however the second is sliced.
+        let config = MovementConfig {
+            accel: f32::INFINITY,
+            friction: f32::INFINITY,
+            turn_follow_speed: f32::INFINITY,
+            ..MovementConfig::new()
+        };
+        let mut moves = Moves::new();
+        moves.up = true;
+
+        with_world(&[], |world| {
+            for frames in [1, 7, 60, 144] {
+                let mut player = player_at(pt2(0.0, 0.0), vec2(1.0, 0.0));
+
+                for _ in 0..frames {
+                    moves.update_player(&mut player, world, &config, false, 1.0 / frames as f32);
+                }
+
+                let distance = player.pos.distance(pt2(0.0, 0.0));
+                assert!(
+                    (distance - config.move_speed).abs() < 0.01,
+                    "{} frames moved {}",
+                    frames,
+                    distance
+                );
+            }
+        });
+
+        moves.up = false;
+        moves.clock = true;
+
+        with_world(&[], |world| {
+            for frames in [1, 7, 60, 144] {
+                let mut player = player_at(pt2(0.0, 0.0), vec2(1.0, 0.0));
+
+                for _ in 0..frames {
+                    moves.update_player(&mut player, world, &config, false, 1.0 / frames as f32);
+                }
+
+                assert_close(
+                    vec2(1.0, 0.0).angle_between(player.look_dir).abs(),
+                    config.turn_speed,
+                );
+            }
+        });
+    }
+
+    #[test]
+    fn recorded_frames_keep_their_actions_and_load_without_them() {
+        let frame = RecordedFrame {
+            dt: 0.016,
+            moves: Moves::new(),
+            actions: vec![RecordedAction::UseDoor, RecordedAction::ToggleLayer(3)],
+        };
+        let line = serde_json::to_string(&frame).unwrap();
+        let loaded: RecordedFrame = serde_json::from_str(&line).unwrap();
+
+        assert!(matches!(
+            loaded.actions[..],
+            [RecordedAction::UseDoor, RecordedAction::ToggleLayer(3)]
+        ));
+
+        // Recordings from before actions were saved have no `actions` field.
+        let old = line.replace(r#","actions":["UseDoor",{"ToggleLayer":3}]"#, "");
+        assert!(!old.contains("actions"));
+        let loaded: RecordedFrame = serde_json::from_str(&old).unwrap();
+        assert!(loaded.actions.is_empty());
+    }
+
+    #[test]
+    fn split_screen_slices_cover_the_window_evenly() {
+        let window = Rect::from_w_h(900.0, 600.0);
+
+        for players in 1..=3 {
+            let areas = split_screen(window, players);
+
+            assert_eq!(areas.len(), players);
+            assert_close(areas[0].left(), window.left());
+            assert_close(areas[players - 1].right(), window.right());
+            for pair in areas.windows(2) {
+                assert_close(pair[0].right(), pair[1].left());
+            }
+            for area in &areas {
+                assert_close(area.w(), window.w() / players as f32);
+                assert_close(area.h(), window.h());
+            }
+        }
+    }
+
+    #[test]
+    fn split_screen_keys_leave_the_toggles_alone() {
+        let toggles = [
+            Key::G,
+            Key::F,
+            Key::M,
+            Key::X,
+            Key::O,
+            Key::B,
+            Key::Y,
+            Key::H,
+            Key::N,
+            Key::C,
+            Key::T,
+            Key::E,
+            Key::R,
+        ];
+
+        for bindings in KeyBindings::split_players() {
+            for key in [
+                bindings.forward,
+                bindings.back,
+                bindings.strafe_left,
+                bindings.strafe_right,
+                bindings.turn_left,
+                bindings.turn_right,
+            ] {
+                assert!(!toggles.contains(&key), "{:?} is also a toggle", key);
+            }
+        }
+    }
+
+    #[test]
+    fn ray_count_steps_back_to_one_ray_per_column() {
+        let columns = 300;
+
+        assert_eq!(next_ray_count(0, columns, false), 150);
+        assert_eq!(next_ray_count(150, columns, true), 0);
+        assert_eq!(next_ray_count(0, columns, true), 600);
+        assert_eq!(next_ray_count(600, columns, false), 0);
+
+        // Steps that would jump over the column count land on it instead.
+        assert_eq!(next_ray_count(200, columns, true), 0);
+        assert_eq!(next_ray_count(400, columns, false), 0);
+
+        assert_eq!(next_ray_count(MIN_RAY_COUNT, columns, false), MIN_RAY_COUNT);
+        assert_eq!(next_ray_count(MAX_RAY_COUNT, columns, true), MAX_RAY_COUNT);
+    }
+
+    // Stand-ins for cast rays, each at a depth that grows with its index.
+    fn rays_at_depths(angles: &ColumnAngles, count: i32) -> Vec<ColumnResult> {
+        angles
+            .rays(count)
+            .columns
+            .iter()
+            .map(|&(ray, angle)| {
+                let depth = 100.0 + 10.0 * ray as f32;
+                let mut result = Ray::from_origin(pt2(0.0, 0.0), Vec2::X.rotate(angle));
+                result.length = Some(depth);
+
+                ColumnResult {
+                    column: ray,
+                    length: Some(depth),
+                    luminosity: Some(1.0),
+                    depth,
+                    ray: result,
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn interpolated_rays_reach_both_edge_columns() {
+        for offset in [0.0, 0.4] {
+            let angles = ColumnAngles::with_offset(60.0, 5, 801.0, offset);
+
+            for count in [MIN_RAY_COUNT, 7, 40, 400] {
+                let rays = angles.rays(count);
+                let (first, last) = (angles.columns[0], *angles.columns.last().unwrap());
+
+                // The outermost rays go through the outermost column centres.
+                assert_close(rays.columns[0].1, first.1);
+                assert_close(rays.columns.last().unwrap().1, last.1);
+
+                let columns = interpolate_rays(&angles, &rays_at_depths(&angles, count));
+
+                assert_eq!(columns.len(), angles.columns.len());
+                assert_eq!(columns[0].column, first.0);
+                assert_eq!(columns.last().unwrap().column, last.0);
+                assert_close(columns[0].depth, 100.0);
+                assert_close(
+                    columns.last().unwrap().depth,
+                    100.0 + 10.0 * (count - 1) as f32,
+                );
+                for pair in columns.windows(2) {
+                    assert!(pair[0].depth <= pair[1].depth + 1e-3);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn sprites_behind_glass_take_the_pane_tint_only_where_it_covers() {
+        let pane = PaneSpan {
+            depth: 50.0,
+            rect: Rect::from_x_y_w_h(0.0, 0.0, 5.0, 100.0),
+            color: rgba(1.0, 0.0, 0.0, 0.25),
+        };
+        let behind = [&pane];
+        let white = rgb(1.0, 1.0, 1.0);
+
+        let tinted = tint_through(white, &behind, 10.0);
+        assert_close(tinted.red, 1.0);
+        assert_close(tinted.green, 0.75);
+        assert_close(tinted.blue, 0.75);
+
+        let above = tint_through(white, &behind, 80.0);
+        assert_close(above.green, 1.0);
+    }
+
+    #[test]
+    fn exported_walls_keep_their_texture() {
+        let map = temp_map(
+            "export",
+            &["0,0 10,0 0.5,0.5,0.5 {dir}/brick.ppm mirror"],
+            &[("brick.ppm", SMALL_TEXTURE)],
+        );
+
+        let walls = MapFile::load(&map.to_string_lossy()).unwrap().boundaries;
+        let exported = map.with_file_name("export.txt");
+        export_map(&walls, &exported.to_string_lossy()).unwrap();
+        let reloaded = MapFile::load(&exported.to_string_lossy())
+            .unwrap()
+            .boundaries;
+        fs::remove_dir_all(map.parent().unwrap()).unwrap();
+
+        assert_eq!(reloaded.len(), 1);
+        assert!(reloaded[0].texture.is_some());
+        assert_eq!(reloaded[0].texture_path, walls[0].texture_path);
+        assert!(reloaded[0].reflective);
+    }
+
+    #[test]
+    fn glass_carries_its_albedo_to_the_ray() {
+        let map = temp_map(
+            "albedo",
+            &[
+                "50,-50 50,50 opacity=0.5 albedo=0.2,1,0.4",
+                "100,-50 100,50",
+            ],
+            &[],
+        );
+        let walls = MapFile::load(&map.to_string_lossy()).unwrap().boundaries;
+        fs::remove_dir_all(map.parent().unwrap()).unwrap();
+
+        assert_eq!(walls[0].material.albedo, rgb(0.2, 1.0, 0.4));
+        assert!(walls[0].to_map_line().contains("albedo=0.2,1,0.4"));
+        assert!(!walls[1].to_map_line().contains("albedo"));
+
+        let player = player_at(pt2(0.0, 0.0), Vec2::X);
+        let ray = with_world(&walls, |world| Ray::cast(&player, 0.0, world, 0, 1000.0));
+
+        assert_eq!(ray.glass.len(), 1);
+        assert_eq!(ray.glass[0].material.albedo, rgb(0.2, 1.0, 0.4));
+    }
+
+    #[test]
+    fn only_a_named_map_counts_as_given() {
+        let parse =
+            |args: &[&str]| Options::from_args(args.iter().map(|arg| arg.to_string())).unwrap();
+
+        let default = parse(&[]);
+        assert_eq!(default.map, MAP_PATH);
+        assert!(!default.map_given);
+
+        assert!(parse(&["level.txt"]).map_given);
+        assert!(parse(&["--map", "level.txt"]).map_given);
+    }
+
+    #[test]
+    fn empty_textures_are_rejected() {
+        // PNG can't hold an empty image, but a header-only PPM decodes to one.
+        let map = temp_map(
+            "empty",
+            &["0,0 10,0 {dir}/empty.ppm"],
+            &[("empty.ppm", b"P6\n0 0\n255\n")],
+        );
+        let texture = map.with_file_name("empty.ppm");
+        assert_eq!(image::open(&texture).unwrap().to_rgba8().width(), 0);
+
+        let walls = MapFile::load(&map.to_string_lossy());
+        fs::remove_dir_all(map.parent().unwrap()).unwrap();
+
+        assert!(walls.is_err());
+    }
+
+    #[test]
+    fn stride_must_be_positive() {
+        let parse = |args: &[&str]| Options::from_args(args.iter().map(|arg| arg.to_string()));
+
+        assert_close(parse(&["--stride", "25"]).unwrap().stride, 25.0);
+        assert!(parse(&["--stride", "0"]).is_err());
+        assert!(parse(&["--stride", "-5"]).is_err());
+        assert!(parse(&["--stride", "inf"]).is_err());
+    }
+
+    #[test]
+    fn adjacent_misses_have_no_outline() {
+        assert!(!is_outline_edge(f32::INFINITY, f32::INFINITY, 20.0));
+        assert!(is_outline_edge(100.0, f32::INFINITY, 20.0));
+        assert!(is_outline_edge(f32::INFINITY, 100.0, 20.0));
+        assert!(is_outline_edge(100.0, 150.0, 20.0));
+        assert!(!is_outline_edge(100.0, 110.0, 20.0));
+    }
+
+    #[test]
+    fn loaded_scenes_get_their_textures_and_limits_back() {
+        let map = temp_map("scene", &[], &[("brick.ppm", SMALL_TEXTURE)]);
+        let mut wall = Boundary::new(pt2(0.0, 0.0), pt2(10.0, 0.0));
+        wall.texture_path = Some(map.with_file_name("brick.ppm").to_string_lossy().into());
+        let scene = |fov: f32, resolution: i32| Scene {
+            player: player_at(pt2(0.0, 0.0), Vec2::X),
+            fov,
+            resolution,
+            boundaries: vec![wall.clone()],
+            circles: Vec::new(),
+            doors: vec![Door::new(wall.clone())],
+            portals: Vec::new(),
+            lights: Vec::new(),
+            spawns: [None; SPAWN_SLOTS],
+        };
+        let load = |scene: Scene| {
+            let path = map.with_file_name("scene.json");
+            fs::write(&path, serde_json::to_string(&scene).unwrap()).unwrap();
+            load_scene(&path.to_string_lossy())
+        };
+
+        let loaded = load(scene(60.0, 5)).unwrap();
+        let bad_fov = load(scene(MAX_FOV + 1.0, 5));
+        let bad_resolution = load(scene(60.0, MAX_RESOLUTION + 1));
+        fs::remove_dir_all(map.parent().unwrap()).unwrap();
+
+        assert!(loaded.boundaries[0].texture.is_some());
+        assert_eq!(loaded.doors.len(), 1);
+        assert!(loaded.doors[0].boundary.texture.is_some());
+        assert!(bad_fov.is_err());
+        assert!(bad_resolution.is_err());
+    }
+
+    #[test]
+    fn a_bad_line_fails_the_whole_map() {
+        let good = temp_map(
+            "good-map",
+            &["0,0 10,0", "door 0,10 10,10", "light 5,5 2"],
+            &[],
+        );
+        let bad = temp_map("bad-map", &["0,0 10,0", "door 0,10 ten,10"], &[]);
+
+        let loaded = MapFile::load(&good.to_string_lossy());
+        let broken = MapFile::load(&bad.to_string_lossy());
+        fs::remove_dir_all(good.parent().unwrap()).unwrap();
+        fs::remove_dir_all(bad.parent().unwrap()).unwrap();
+
+        let loaded = loaded.unwrap();
+        assert_eq!(loaded.boundaries.len(), 1);
+        assert_eq!(loaded.doors.len(), 1);
+        assert_eq!(loaded.lights.len(), 1);
+        assert_eq!(broken.err().unwrap().kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn zero_length_walls_are_rejected() {
+        let map = temp_map("zero-length", &["0,0 10,0", "5,5 5,5"], &[]);
+        let walls = MapFile::load(&map.to_string_lossy());
+        fs::remove_dir_all(map.parent().unwrap()).unwrap();
+
+        let err = walls.err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().contains(":2:"), "{}", err);
+    }
 }